@@ -0,0 +1,191 @@
+use crate::vorbis;
+use id3::Tag;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+// Metadata block types handled here, others are copied as they are
+const PADDING: u8 = 1;
+const VORBIS_COMMENT: u8 = 4;
+const PICTURE: u8 = 6;
+
+// Padding added when the metadata no longer fits and the whole file is rewritten
+const PADDING_SIZE: usize = 4096;
+
+// Single FLAC metadata block
+struct Block {
+    kind: u8,
+    data: Vec<u8>,
+}
+
+// FLAC metadata layout
+struct Metadata {
+    start: u64,                                                     // Position of "fLaC", after a possible ID3v2 tag
+    audio: u64,                                                     // Position of the first audio frame
+    blocks: Vec<Block>,
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Read metadata blocks from FLAC file
+//
+// Inputs
+// file - opened FLAC file
+//
+// Return: Metadata blocks and their position in the file
+//////////////////////////////////////////////////////////////////////////////////////
+fn read_metadata(file: &mut fs::File) -> io::Result<Metadata> {
+    let mut header = [0u8; 10];
+    let mut start: u64 = 0;
+
+    // Some taggers put an ID3v2 tag in front of the stream
+    file.read_exact(&mut header)?;
+    if &header[0..3] == b"ID3" {
+        let footer: u64 = if header[5] & 0x10 != 0 { 10 } else { 0 };
+        start = crate::syncsafe(&header[6..10]) as u64 + 10 + footer;
+    }
+
+    let mut magic = [0u8; 4];
+    file.seek(SeekFrom::Start(start))?;
+    file.read_exact(&mut magic)?;
+
+    if &magic != b"fLaC" {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a FLAC file"));
+    }
+
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut last = false;
+
+    while !last {
+        let mut block_header = [0u8; 4];
+        file.read_exact(&mut block_header)?;
+
+        last = block_header[0] & 0x80 != 0;
+        let size = u32::from_be_bytes([0, block_header[1], block_header[2], block_header[3]]) as usize;
+        let mut data = vec![0u8; size];
+        file.read_exact(&mut data)?;
+
+        blocks.push(Block { kind: block_header[0] & 0x7f, data });
+    }
+
+    let audio = file.stream_position()?;
+    Ok(Metadata { start, audio, blocks })
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Read Vorbis comments and pictures from FLAC file
+//
+// Inputs
+// path - path to FLAC file
+//
+// Return: ID3 tag holding the same information
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn read(path: &str) -> io::Result<Tag> {
+    let metadata = read_metadata(&mut fs::File::open(path)?)?;
+    let mut fields: Vec<(String, String)> = Vec::new();
+    let mut pictures = Vec::new();
+
+    for block in &metadata.blocks {
+        if block.kind == VORBIS_COMMENT {
+            if let Some(comments) = vorbis::parse(&block.data) { fields.extend(comments.fields); }
+        } else if block.kind == PICTURE {
+            if let Some(picture) = vorbis::parse_picture(&block.data) { pictures.push(picture); }
+        }
+    }
+
+    Ok(vorbis::to_tag(&fields, &pictures))
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Write tag to FLAC file as Vorbis comments and PICTURE blocks
+//
+// Inputs
+// path - path to FLAC file
+// tag  - ID3 tag handler, frames without a Vorbis comment field are dropped
+//
+// Return: Error if the file can't be read or written
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn write(path: &str, tag: &Tag) -> io::Result<()> {
+    let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+    let metadata = read_metadata(&mut file)?;
+    let (fields, pictures) = vorbis::from_tag(tag);
+
+    // Vendor string is kept, as it tells the encoder of the audio
+    let vendor = metadata.blocks.iter().filter(|block| block.kind == VORBIS_COMMENT).find_map(|block| vorbis::parse(&block.data))
+        .map(|comments| comments.vendor).unwrap_or_else(|| String::from("id3handler"));
+
+    let mut blocks: Vec<Block> = Vec::new();
+
+    for block in metadata.blocks {
+        if block.kind != VORBIS_COMMENT && block.kind != PICTURE && block.kind != PADDING { blocks.push(block); }
+    }
+
+    blocks.push(Block { kind: VORBIS_COMMENT, data: vorbis::build(&vorbis::Comments { vendor, fields }) });
+
+    for picture in &pictures {
+        blocks.push(Block { kind: PICTURE, data: vorbis::build_picture(picture) });
+    }
+
+    let space = (metadata.audio - metadata.start) as usize - 4;
+    let used: usize = blocks.iter().map(|block| block.data.len() + 4).sum();
+
+    // Old metadata area is reused if the new blocks fit in it with a padding block
+    if used == space || used + 4 <= space {
+        if used < space { blocks.push(Block { kind: PADDING, data: vec![0u8; space - used - 4] }); }
+
+        file.seek(SeekFrom::Start(metadata.start + 4))?;
+        file.write_all(&build_metadata(&blocks)?)?;
+        return Ok(());
+    }
+
+    blocks.push(Block { kind: PADDING, data: vec![0u8; PADDING_SIZE] });
+
+    let mut audio: Vec<u8> = Vec::new();
+    let mut head = vec![0u8; metadata.start as usize + 4];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut head)?;
+    file.seek(SeekFrom::Start(metadata.audio))?;
+    file.read_to_end(&mut audio)?;
+
+    drop(file);
+    crate::replace_file(path, |output| {
+        output.write_all(&head)?;
+        output.write_all(&build_metadata(&blocks)?)?;
+        output.write_all(&audio)
+    })
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Remove all Vorbis comments and pictures from FLAC file
+//
+// Inputs
+// path - path to FLAC file
+//
+// Return: Error if the file can't be read or written
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn remove(path: &str) -> io::Result<()> {
+    write(path, &Tag::new())
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Build metadata blocks with their headers
+//
+// Inputs
+// blocks - metadata blocks, the last one is marked as last
+//
+// Return: Block data, or error if a block is too big
+//////////////////////////////////////////////////////////////////////////////////////
+fn build_metadata(blocks: &[Block]) -> io::Result<Vec<u8>> {
+    let mut data: Vec<u8> = Vec::new();
+
+    for (i, block) in blocks.iter().enumerate() {
+        if block.data.len() >= 1 << 24 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "FLAC metadata block too big"));
+        }
+
+        let last: u8 = if i + 1 == blocks.len() { 0x80 } else { 0 };
+        let size = (block.data.len() as u32).to_be_bytes();
+        data.extend_from_slice(&[block.kind | last, size[1], size[2], size[3]]);
+        data.extend_from_slice(&block.data);
+    }
+
+    Ok(data)
+}
@@ -0,0 +1,127 @@
+use id3::{Tag, TagLike};
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+// ID3v1 tag is always the last 128 bytes of the file
+pub const TAG_SIZE: usize = 128;
+
+// ID3v1 genres in the order of their numeric values, with the Winamp extensions
+pub const GENRES: [&str; 148] = [
+    "Blues", "Classic Rock", "Country", "Dance", "Disco", "Funk", "Grunge", "Hip-Hop", "Jazz", "Metal", "New Age",
+    "Oldies", "Other", "Pop", "R&B", "Rap", "Reggae", "Rock", "Techno", "Industrial", "Alternative", "Ska",
+    "Death Metal", "Pranks", "Soundtrack", "Euro-Techno", "Ambient", "Trip-Hop", "Vocal", "Jazz+Funk", "Fusion",
+    "Trance", "Classical", "Instrumental", "Acid", "House", "Game", "Sound Clip", "Gospel", "Noise", "Alternative Rock",
+    "Bass", "Soul", "Punk", "Space", "Meditative", "Instrumental Pop", "Instrumental Rock", "Ethnic", "Gothic",
+    "Darkwave", "Techno-Industrial", "Electronic", "Pop-Folk", "Eurodance", "Dream", "Southern Rock", "Comedy", "Cult",
+    "Gangsta", "Top 40", "Christian Rap", "Pop/Funk", "Jungle", "Native US", "Cabaret", "New Wave", "Psychadelic",
+    "Rave", "Showtunes", "Trailer", "Lo-Fi", "Tribal", "Acid Punk", "Acid Jazz", "Polka", "Retro", "Musical",
+    "Rock & Roll", "Hard Rock", "Folk", "Folk-Rock", "National Folk", "Swing", "Fast Fusion", "Bebob", "Latin",
+    "Revival", "Celtic", "Bluegrass", "Avantgarde", "Gothic Rock", "Progressive Rock", "Psychedelic Rock",
+    "Symphonic Rock", "Slow Rock", "Big Band", "Chorus", "Easy Listening", "Acoustic", "Humour", "Speech", "Chanson",
+    "Opera", "Chamber Music", "Sonata", "Symphony", "Booty Bass", "Primus", "Porn Groove", "Satire", "Slow Jam", "Club",
+    "Tango", "Samba", "Folklore", "Ballad", "Power Ballad", "Rhytmic Soul", "Freestyle", "Duet", "Punk Rock",
+    "Drum Solo", "Acapella", "Euro-House", "Dance Hall", "Goa", "Drum & Bass", "Club-House", "Hardcore", "Terror",
+    "Indie", "BritPop", "Negerpunk", "Polsk Punk", "Beat", "Christian Gangsta", "Heavy Metal", "Black Metal",
+    "Crossover", "Contemporary C", "Christian Rock", "Merengue", "Salsa", "Thrash Metal", "Anime", "JPop", "SynthPop",
+];
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Build ID3v1.1 tag from ID3v2 tag data
+//
+// Inputs
+// tag   - ID3v2 tag handler
+// genre - ID3v1 genre index used if the genre of the tag is not in the table, 255 if unknown
+//
+// Return: 128 byte ID3v1.1 tag
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn build(tag: &Tag, genre: u8) -> [u8; TAG_SIZE] {
+    let mut data = [0u8; TAG_SIZE];
+    let year = tag.year().or_else(|| tag.date_recorded().map(|date| date.year)).map(|year| format!("{:04}", year)).unwrap_or_default();
+    let comment = tag.comments().next().map(|comment| comment.text.clone()).unwrap_or_default();
+
+    data[0..3].copy_from_slice(b"TAG");
+    set_text(&mut data[3..33],  tag.title().unwrap_or(""));
+    set_text(&mut data[33..63], tag.artist().unwrap_or(""));
+    set_text(&mut data[63..93], tag.album().unwrap_or(""));
+    set_text(&mut data[93..97], &year);
+
+    // ID3v1.1 takes the last two bytes of the comment for a zero byte and the track number
+    match tag.track() {
+        Some(track) if track > 0 && track < 256 => {
+            set_text(&mut data[97..125], &comment);
+            data[126] = track as u8;
+        }
+        _ => set_text(&mut data[97..127], &comment),
+    }
+
+    // Only the first of several genres fits
+    let first = tag.genre().map(crate::parse_genre).and_then(|names| names.split(" / ").next().and_then(genre_index));
+    data[127] = first.unwrap_or(genre);
+    data
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Write string to a fixed size field as Latin-1, truncating it if needed
+//
+// Inputs
+// field - target bytes, left zero filled
+// input - text to write, characters outside Latin-1 are replaced with '?'
+//////////////////////////////////////////////////////////////////////////////////////
+fn set_text(field: &mut [u8], input: &str) {
+    for (pos, c) in input.chars().take(field.len()).enumerate() {
+        field[pos] = if (c as u32) < 0x100 { c as u32 as u8 } else { b'?' };
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Read raw ID3v1 tag from the end of the file
+//
+// Inputs
+// path - path to audio file
+//
+// Return: 128 byte tag, or None if the file has no ID3v1 tag
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn read_raw(path: &str) -> Option<[u8; TAG_SIZE]> {
+    let mut file = fs::File::open(path).ok()?;
+    let mut data = [0u8; TAG_SIZE];
+
+    file.seek(SeekFrom::End(-(TAG_SIZE as i64))).ok()?;
+    file.read_exact(&mut data).ok()?;
+
+    if &data[0..3] == b"TAG" { Some(data) } else { None }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Write ID3v1 tag, replacing the existing one or appending it to the end of the file
+//
+// Inputs
+// path - path to audio file
+// data - 128 byte tag
+//
+// Return: Error if the file can't be written
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn write(path: &str, data: &[u8; TAG_SIZE]) -> std::io::Result<()> {
+    let mut file = fs::OpenOptions::new().write(true).open(path)?;
+
+    if read_raw(path).is_some() {
+        file.seek(SeekFrom::End(-(TAG_SIZE as i64)))?;
+    } else {
+        file.seek(SeekFrom::End(0))?;
+    }
+
+    file.write_all(data)
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Get genre name of ID3v1 genre number
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn genre_name(index: usize) -> Option<&'static str> {
+    GENRES.get(index).copied()
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Get ID3v1 genre number of genre name, case is ignored
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn genre_index(name: &str) -> Option<u8> {
+    GENRES.iter().position(|genre| genre.eq_ignore_ascii_case(name.trim())).map(|index| index as u8)
+}
@@ -0,0 +1,80 @@
+//////////////////////////////////////////////////////////////////////////////////////
+// Get MIME type of image from its magic bytes
+//
+// Inputs
+// data - image file data
+//
+// Return: "image/jpeg" or "image/png", or None for other formats
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn mime_type(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(&[0xff, 0xd8, 0xff]) { Some("image/jpeg")
+    } else if data.starts_with(b"\x89PNG\r\n\x1a\n") { Some("image/png")
+    } else { None }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Get file extension for image MIME type
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn extension(mime_type: &str) -> &'static str {
+    if mime_type == "image/png" { "png" } else { "jpg" }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Read image dimensions from JPEG or PNG headers
+//
+// Inputs
+// data - image file data
+//
+// Return: Width and height, or None if the image is broken or of another format
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    match mime_type(data)? {
+        "image/png" => png_dimensions(data),
+        _ => jpeg_dimensions(data),
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Read dimensions from the IHDR chunk, which is always the first chunk of PNG image
+//////////////////////////////////////////////////////////////////////////////////////
+fn png_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    if data.get(12..16)? != b"IHDR" { return None; }
+
+    let width = u32::from_be_bytes(data.get(16..20)?.try_into().ok()?);
+    let height = u32::from_be_bytes(data.get(20..24)?.try_into().ok()?);
+    Some((width, height))
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Read dimensions from the start of frame segment of JPEG image
+//////////////////////////////////////////////////////////////////////////////////////
+fn jpeg_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let mut pos: usize = 2;
+
+    loop {
+        // Markers may be preceded by any number of fill bytes
+        while *data.get(pos)? == 0xff && *data.get(pos + 1)? == 0xff { pos += 1; }
+        if *data.get(pos)? != 0xff { return None; }
+
+        let marker = *data.get(pos + 1)?;
+
+        // Markers without a segment
+        if marker == 0x01 || (0xd0..=0xd7).contains(&marker) {
+            pos += 2;
+            continue;
+        }
+
+        // Start of frame markers, other than DHT, JPG and DAC that share the range
+        if (0xc0..=0xcf).contains(&marker) && marker != 0xc4 && marker != 0xc8 && marker != 0xcc {
+            let height = u16::from_be_bytes([*data.get(pos + 5)?, *data.get(pos + 6)?]) as u32;
+            let width = u16::from_be_bytes([*data.get(pos + 7)?, *data.get(pos + 8)?]) as u32;
+            return Some((width, height));
+        }
+
+        // Image data starts without a frame header, the image is broken
+        if marker == 0xd9 || marker == 0xda { return None; }
+
+        let len = u16::from_be_bytes([*data.get(pos + 2)?, *data.get(pos + 3)?]) as usize;
+        pos += 2 + len;
+    }
+}
@@ -1,24 +1,126 @@
-use id3::{Tag, TagLike, Version};
+use id3::frame::{Content, ExtendedText, Picture, PictureType};
+use id3::{Encoding, Frame, Tag, TagLike, Timestamp, Version};
+use regex::Regex;
 use std::env;
 use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicU32, Ordering};
 use chrono::Datelike;
 
+mod flac;
+mod id3v1;
+mod image;
+mod mp4;
+mod ogg;
+mod riff;
+mod vorbis;
+
+// File extensions handled when walking through directories
+const AUDIO_EXTENSIONS: [&str; 11] = ["mp3", "flac", "ogg", "oga", "opus", "m4a", "m4b", "wav", "aif", "aiff", "aifc"];
+
+// File extensions of album folder files that are never audio, so their content isn't checked
+const OTHER_EXTENSIONS: [&str; 20] = ["jpg", "jpeg", "png", "gif", "bmp", "webp", "tif", "tiff", "txt", "nfo", "log", "cue", "m3u", "m3u8", "pls", "pdf", "sfv", "md5", "ini", "db"];
+
+// Bytes read from the start of a file for detecting its format, enough for two MPEG frames
+const SNIFF_SIZE: u64 = 8192;
+
+// Tag items that can be given one by one with the set command
+const SET_FIELDS: [&str; 8] = ["artist", "album", "title", "year", "track", "disc", "genre", "albumartist"];
+
+// Counter making the names of temporary files unique within this process
+static TEMP_COUNTER: AtomicU32 = AtomicU32::new(0);
+
+// Disc part of a folder or album name, like "CD1", "Disc 2" or "Album (Disk 3)"
+static DISC_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)^(.*?)(?:^|[\s_\-(\[]+)(?:cd|disc|disk)[\s_]*(\d{1,2})[)\]]?$").unwrap());
+
+// Highest accepted track number, box sets and audiobooks can have hundreds of tracks
+const MAX_TRACK: i32 = 9999;
+
+// Highest track number taken from a filename, as four digits are usually a year like "1999 - Title"
+const MAX_FILE_TRACK: i32 = 999;
+
 // Basic ID3 tag information
+#[derive(Clone)]
 struct ID3TagInfo {
     artist: String, title: String, album: String,
     track: u32, year: i32,
+    track_total: u32,                                               // Total of TRCK, 0 if not known
+    disc: u32, disc_total: u32,                                     // TPOS, 0 if not known
+    genre: String,
+    album_artist: String,                                           // TPE2, "empty" if not known
+    compilation: bool,                                              // TCMP, only written when set
+    extra: Vec<(String, String)>,                                   // TXXX user defined frames as description and value
+    source: String,                                                 // Tag version the data was read from, or "empty"
 }
 
 // Album info struct
 struct AlbumInfo {
     artist: String, album: String,
     year: i32,
+    disc: u32,                                                      // From a disc folder like "CD1", 0 if none
 }
 
 // Track info struct
 struct TrackInfo {
     title: String,
     track: u32,
+    disc: u32,                                                      // From a number like "101", 0 if none
+}
+
+// Path template item, either literal text or a %field% placeholder with optional format
+enum PatternToken {
+    Text(String),
+    Field(String, String),
+}
+
+// Outcome of handling a single file
+#[derive(PartialEq)]
+enum FileStatus {
+    Updated, Skipped, Failed,
+}
+
+// Counters for handled files
+struct BatchSummary {
+    updated: u32, skipped: u32, failed: u32,
+}
+
+// Output settings for writing tags
+struct WriteOptions {
+    version: Option<Version>,                                       // None keeps the version of the existing tag
+    convert: bool,                                                  // Version was given, so matching tags of other versions are rewritten too
+    encoding: Option<Encoding>,                                     // None uses the default of the version
+    id3v1: bool,                                                    // Write also ID3v1 tag at the end of the file
+    pad_tracks: bool,                                               // Pad track numbers with zeros to the width of the total
+}
+
+// Library tree settings for organizing files
+struct LibraryInfo {
+    root: String,
+    layout: Vec<PatternToken>,
+    copy: bool,
+}
+
+// Cover art settings for embedding and extracting folder images
+struct ArtOptions {
+    names: Vec<String>,                                             // Image names in priority order, like "cover.*" or "front.png"
+    max_size: u64,                                                  // Largest embedded image in bytes, 0 for no limit
+    max_dimensions: Option<(u32, u32)>,                             // Largest embedded width and height
+    replace: bool,                                                  // Replace existing front covers and image files
+}
+
+// Report of frames handled while updating a tag
+struct FrameReport {
+    kept: Vec<String>, changed: Vec<String>, added: Vec<String>,
+}
+
+// ID3v2 tag layout as stored in the file
+struct RawTag {
+    version: u8,                                                    // Major version, 2 to 4
+    size: usize,                                                    // Full tag size with header and footer
+    padding: Option<usize>,                                         // None if the frames couldn't be scanned
+    frames: Vec<(String, usize, u8)>,                               // Frame ID, data size and first data byte
 }
 
 //////////////////////////////////////////////////////////////////////////////////////
@@ -34,26 +136,36 @@ impl AlbumInfo {
     // Return: parsed information struct from the string
     //////////////////////////////////////////////////////////////////////////////////////
     pub fn parse(path: &str) -> AlbumInfo {
-        let mut split = find_last_char(path.as_bytes(), b'/');
+        let mut split = find_last_char(path, '/');
         let mut filename: String = format!("{}", path);
 
         // Check if path has folders, and get the deepest folder only
         if split > 0 {
             filename.replace_range(split..filename.len(), "");
-            split = find_last_char(filename.as_bytes(), b'/');
+            split = find_last_char(&filename, '/');
             if split > 0 {
                 filename.replace_range(0..split+1, "");
             }
         }
 
+        // Disc folders are not part of the album name, and a folder like "CD1" has the album as its parent
+        let mut disc: u32 = 0;
+        if let Some((rest, number)) = parse_disc(&filename) {
+            let parts: Vec<&str> = path.split('/').collect();
+            disc = number;
+
+            if !rest.is_empty() {      filename = rest;
+            } else if parts.len() > 2 { filename = String::from(parts[parts.len() - 3]); }
+        }
+
         let mut _artist: String = format!("{}", filename);
         let mut _year:   String = format!("{}", filename);
         let mut _album:  String = format!("{}", filename);
         let current_date = chrono::Utc::now();
-        let minuses = get_char_count(filename.as_bytes(), b'-');
+        let minuses = get_char_count(&filename, '-');
 
-        if filename.len() > 10 || minuses > 0 {
-            let first_minus = find_first_char(filename.as_bytes(), b'-');
+        if filename.chars().count() > 10 || minuses > 0 {
+            let first_minus = find_first_char(&filename, '-');
             let mut pos2 = find_verified_number(&filename, 1800, current_date.year() as usize, 4, first_minus);
             if pos2 < 0 && first_minus > 0 { pos2 = find_verified_number(&filename, 1800, current_date.year() as usize, 4, 0); }
             let pos = pos2 as usize;
@@ -64,15 +176,15 @@ impl AlbumInfo {
                 _year.replace_range(pos+4.._year.len(), "");
                 _year.replace_range(0..pos, "");
             } else if minuses > 1 {                                         // If there are more than one minus, there's probably a year
-                 let first = find_first_char(filename.as_bytes(), b'-');
-                 let last  = find_last_char(filename.as_bytes(), b'-');
+                 let first = find_first_char(&filename, '-');
+                 let last  = find_last_char(&filename, '-');
 
                 _artist.replace_range(first.._artist.len(), "");
                 _album.replace_range(0..last+1, "");
                 _year.replace_range(last.._year.len(), "");
                 _year.replace_range(0..first+1, "");
             } else if minuses == 1 {                                        // Only one minus, only artist and album name
-                 let first = find_first_char(filename.as_bytes(), b'-');
+                 let first = find_first_char(&filename, '-');
                 _year   = format!("{}", current_date.year());
                 _album.replace_range(0..first+1, "");
                 _artist.replace_range(first.._artist.len(), "");
@@ -89,7 +201,7 @@ impl AlbumInfo {
 
         if _artist == "." && _album == "empty" { _artist = format!("empty"); }
 
-        AlbumInfo { artist: _artist, year: _year.parse().unwrap(), album: _album }
+        AlbumInfo { artist: _artist, year: _year.parse().unwrap(), album: _album, disc }
     }
 }
 
@@ -107,22 +219,33 @@ impl TrackInfo {
     //////////////////////////////////////////////////////////////////////////////////////
     pub fn parse(file: &str) -> TrackInfo {
         let mut filename: String = format!("{}", file);
-        let mut pos = find_last_char(filename.as_bytes(), b'.');
-        let split = find_last_char(filename.as_bytes(), b'/');
+        let mut pos = find_last_char(&filename, '.');
+        let split = find_last_char(&filename, '/');
 
         // Remove file extension
-        filename.replace_range(pos..filename.len(), "");
+        if pos > split {
+            filename.replace_range(pos..filename.len(), "");
+        }
         if split > 0 {
             filename.replace_range(0..split, "");
         }
 
+        // Three digit numbers like "101" are disc and track numbers of multi-disc sets
+        let name = remove_whitespace(&filename);
+        let digits: String = name.chars().take_while(|c| c.is_ascii_digit()).collect();
+        if digits.len() == 3 && !digits.starts_with('0') && !digits.ends_with("00") {
+            return TrackInfo { title: remove_whitespace(&name[3..]), track: digits[1..].parse().unwrap(), disc: digits[..1].parse().unwrap() };
+        } else if digits.len() == 3 {
+            return TrackInfo { title: remove_whitespace(&name[3..]), track: verify_number(&digits, 1, MAX_FILE_TRACK).parse().unwrap(), disc: 0 };
+        }
+
         let mut _track: String = format!("{}", filename);
         let mut _title: String = format!("{}", filename);
 
         // Filename probably as a number in it
-        if filename.len() > 5 {
-            let pos2 = find_number(filename.as_bytes(), 0, 2);
-            if pos2 < 0 { find_number(filename.as_bytes(), 0, 1); }
+        if filename.chars().count() > 5 {
+            let pos2 = find_number(&filename, 0, 2);
+            if pos2 < 0 { find_number(&filename, 0, 1); }
             pos = pos2 as usize;
 
             if pos2 >= 0 {                                          // Separate number and track
@@ -130,7 +253,7 @@ impl TrackInfo {
                 _track.replace_range(pos+2.._track.len(), "");
                 _track.replace_range(0..pos, "");
             } else {                                                // Se if there's a minus, and try to separate by that
-                pos = find_first_char(_track.as_bytes(), b'-');
+                pos = find_first_char(&_track, '-');
 
                 if pos > 0 {
                     _title.replace_range(0..pos+1, "");
@@ -140,10 +263,157 @@ impl TrackInfo {
         }
 
         _title = remove_whitespace(&_title);
-        _track = verify_number(&_track, 1, 99);
+        _track = verify_number(&_track, 1, MAX_FILE_TRACK);
+
+        TrackInfo { title: _title, track: _track.parse().unwrap(), disc: 0 }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Split path template into literal text and %field% tokens
+//
+// Inputs
+// pattern - path template, "%%" is a literal percent sign
+//
+// Return: List of template tokens
+//////////////////////////////////////////////////////////////////////////////////////
+fn parse_pattern_tokens(pattern: &str) -> Vec<PatternToken> {
+    let mut tokens: Vec<PatternToken> = Vec::new();
+    let mut text = String::new();
+    let mut rest = pattern;
+
+    while let Some(start) = rest.find('%') {
+        text.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        match rest.find('%') {
+            Some(0) => { text.push('%'); rest = &rest[1..]; }
+            Some(end) => {
+                if !text.is_empty() { tokens.push(PatternToken::Text(text.clone())); text.clear(); }
+
+                // Formatting options, like %track:02%, are only used when rendering
+                let (name, format) = rest[..end].split_once(':').unwrap_or((&rest[..end], ""));
+                tokens.push(PatternToken::Field(name.to_lowercase(), String::from(format)));
+                rest = &rest[end + 1..];
+            }
+            None => { text.push('%'); }
+        }
+    }
+
+    text.push_str(rest);
+    if !text.is_empty() { tokens.push(PatternToken::Text(text)); }
+
+    tokens
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Get the part of the path a template is matched against
+//
+// Inputs
+// input  - path to audio file
+// levels - number of path components in the template
+//
+// Return: Deepest path components without the file extension
+//////////////////////////////////////////////////////////////////////////////////////
+fn get_pattern_source(input: &str, levels: usize) -> String {
+    let mut source: &str = input;
+
+    if let Some(split) = source.rfind('.') {
+        if !source[split..].contains('/') { source = &source[..split]; }
+    }
+
+    let parts: Vec<&str> = source.split('/').collect();
+    let first = parts.len().saturating_sub(levels);
+
+    parts[first..].join("/")
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Match template tokens against input, backtracking over the field lengths
+//
+// Inputs
+// tokens - template tokens
+// input  - string to match against
+// fields - matched field names and values, filled on success
+//
+// Return: true if the whole input matches the template
+//////////////////////////////////////////////////////////////////////////////////////
+fn match_pattern(tokens: &[PatternToken], input: &str, fields: &mut Vec<(String, String)>) -> bool {
+    if tokens.is_empty() { return input.is_empty(); }
+
+    match &tokens[0] {
+        PatternToken::Text(text) => {
+            input.starts_with(text.as_str()) && match_pattern(&tokens[1..], &input[text.len()..], fields)
+        }
+        PatternToken::Field(name, _) => {
+            let numeric = name == "year" || name == "track" || name == "disc";
+
+            for (pos, c) in input.char_indices() {
+                if c == '/' || (numeric && !c.is_ascii_digit()) { break; }
+
+                let end = pos + c.len_utf8();
+                fields.push((name.clone(), String::from(&input[..end])));
+
+                if match_pattern(&tokens[1..], &input[end..], fields) { return true; }
+                fields.pop();
+            }
+
+            false
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Render template with tag data
+//
+// Inputs
+// tokens - template tokens
+// info   - tag data, field values are sanitized to be usable in file names
+//
+// Return: Rendered string, or Err with the name of the first field without data
+//////////////////////////////////////////////////////////////////////////////////////
+fn render_pattern(tokens: &[PatternToken], info: &ID3TagInfo) -> Result<String, String> {
+    let mut rendered = String::new();
+
+    for token in tokens {
+        match token {
+            PatternToken::Text(text) => rendered.push_str(text),
+            PatternToken::Field(name, format) => {
+                let value = match info.get_field(name) {
+                    Some(value) => value,
+                    None => return Err(name.clone()),
+                };
+
+                // Numeric format like "02" pads the value with zeros
+                let width: usize = format.parse().unwrap_or(0);
+                let value = if format.starts_with('0') && value.chars().all(|c| c.is_ascii_digit()) {
+                    format!("{:0>width$}", value, width = width)
+                } else {
+                    value
+                };
 
-        TrackInfo { title: _title, track: _track.parse().unwrap() }
+                rendered.push_str(&sanitize_filename(&value));
+            }
+        }
     }
+
+    Ok(rendered)
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Replace characters that are illegal in file names on common filesystems
+//
+// Inputs
+// input - file or directory name
+//
+// Return: Name with illegal characters replaced by '_', and without trailing dots or spaces
+//////////////////////////////////////////////////////////////////////////////////////
+fn sanitize_filename(input: &str) -> String {
+    let cleaned: String = input.chars().map(|c| {
+        if c.is_control() || "<>:\"/\\|?*".contains(c) { '_' } else { c }
+    }).collect();
+
+    String::from(cleaned.trim_end_matches(['.', ' ']).trim_start())
 }
 
 //////////////////////////////////////////////////////////////////////////////////////
@@ -157,11 +427,10 @@ impl TrackInfo {
 // Return: String between the given bounds
 //////////////////////////////////////////////////////////////////////////////////////
 fn get_string_between(input: &str, first: usize, last: usize) -> String {
-    let mut gutted: String = format!("{}", input);
-
-    gutted.replace_range(last..input.len(), "");
-    gutted.replace_range(0..first, "");
-    gutted
+    match input.get(first..last) {
+        Some(gutted) => String::from(gutted),
+        None => String::new(),
+    }
 }
 
 //////////////////////////////////////////////////////////////////////////////////////
@@ -182,7 +451,7 @@ fn find_verified_number(input: &str, min: usize, max: usize, len: usize, spos: u
     let mut ret: i32 = -1;
 
     while _run == true {
-        let pos = find_number(input.as_bytes(), start, len);
+        let pos = find_number(input, start, len);
         if pos < 0 { _run = false; break; }
         let pos2 = pos as usize;
 
@@ -201,24 +470,28 @@ fn find_verified_number(input: &str, min: usize, max: usize, len: usize, spos: u
 //
 // Inputs
 // input     - input string
-// start_pos - possible start position for the string
-// size      - number of chars the wanted length of the number
+// start_pos - possible start position for the string, in bytes
+// size      - number of digits the wanted length of the number
 //
-// Return: Position where the seeked value was found, or -1 if not found
+// Return: Byte position where the seeked value was found, or -1 if not found
 //////////////////////////////////////////////////////////////////////////////////////
-fn find_number(input: &[u8], start_pos: usize, size: usize) -> i32 {
+fn find_number(input: &str, start_pos: usize, size: usize) -> i32 {
     let mut count: usize = 0;
     let mut start: usize = start_pos;
     let mut found: bool = false;
     let mut total: usize = 0;
 
-    for i in start_pos..input.len() {
-        let compare = input[i] as char;
+    let rest = match input.get(start_pos..) {
+        Some(rest) => rest,
+        None => return -1,
+    };
 
-        if compare.is_numeric() { count += 1
+    // Only ASCII digits are accepted, as chars like '²' or '三' are numeric but can't be parsed
+    for (i, compare) in rest.char_indices() {
+        if compare.is_ascii_digit() { count += 1
         } else { total = count; count = 0; }
 
-        if count == 1 { start = i }
+        if count == 1 { start = start_pos + i }
 
         if total == size { found = true; break; }
     }
@@ -251,352 +524,2404 @@ fn verify_number(input: &str, min: i32, max: i32) -> String  {
 }
 
 //////////////////////////////////////////////////////////////////////////////////////
-// Read wanted data from ID3 stream
+// Validate tag item given from the commandline
 //
 // Inputs
-// path - path to audio file
-// src  - type of wanted value
+// name  - item name
+// value - item value as string
 //
-// Return: wanted value in string, or "empty" if no tag is found
+// Return: Ok if the value is usable, or Err with the reason
 //////////////////////////////////////////////////////////////////////////////////////
-fn handle_tag_string(path: &str, src: &str) -> String {
-    let mut tag_in: Tag = Default::default();
+fn validate_field(name: &str, value: &str) -> Result<(), String> {
+    let current_date = chrono::Utc::now();
 
-    let mut do_steps = || -> Result<(), Box<dyn std::error::Error>> {
-        tag_in = Tag::read_from_path(path)?;
-        Ok(())
-    };
+    if name == "year" && verify_number(value, 1800, current_date.year()) == "0" {
+        return Err(format!("Invalid year '{}', expected a number between 1800 and {}", value, current_date.year()));
+    }
 
-    if let Err(_err) = do_steps() {
-        return format!("empty");
-    }
-
-    let tag = Tag::read_from_path(path).unwrap();
-    let mut input: String;
-    let mut value: bool = false;
-
-    if        src == "album"  { input = format!("{:?}", tag.album());
-    } else if src == "artist" { input = format!("{:?}", tag.artist());
-    } else if src == "title"  { input = format!("{:?}", tag.title());
-    } else if src == "year" {
-        value = true;
-        input = format!("{:?}", tag.year());
-    } else if src == "track" {
-        value = true;
-        input = format!("{:?}", tag.track());
-    } else { input = format!("empty"); }
-
-    if value {
-        if input.len() > 6 {
-            input.replace_range(0..5, "");
-            input.truncate(input.len() -1);
-        } else {
-            input = format!("empty");
-        }
-    } else {
-        if input.len() > 8 {
-            input.replace_range(0..6, "");
-            input.truncate(input.len() -2);
-        } else {
-            input = format!("empty");
-        }
+    let (number, total) = value.split_once('/').unwrap_or((value, "1"));
+    if name == "track" && (verify_number(number, 1, MAX_TRACK) == "0" || verify_number(total, 1, MAX_TRACK) == "0") {
+        return Err(format!("Invalid track '{}', expected a number between 1 and {}, optionally with the total like 3/12", value, MAX_TRACK));
+    }
+
+    let (disc, total) = value.split_once('/').unwrap_or((value, "1"));
+    if name == "disc" && (verify_number(disc, 1, 99) == "0" || verify_number(total, 1, 99) == "0") {
+        return Err(format!("Invalid disc '{}', expected a number between 1 and 99, optionally with the total like 2/3", value));
+    }
+
+    if value.trim().is_empty() {
+        return Err(format!("Empty value given for {}", name));
     }
 
-    remove_whitespace(&input)
+    Ok(())
 }
 
 //////////////////////////////////////////////////////////////////////////////////////
-// Find first position of the comparison character
+// Parse disc number from folder name, like "CD1", "Disc 2" or "Album (Disc 2)"
 //
 // Inputs
-// input   - input string
-// compare - comparison character
+// name - folder name
 //
-// Return: First position of the found character
+// Return: Folder name without the disc part and the disc number, or None if the folder isn't a disc folder
 //////////////////////////////////////////////////////////////////////////////////////
-fn find_first_char(input: &[u8], compare: u8) -> usize {
-    let mut pos: usize = 0;
-
-    for i in 0..input.len() {
-        if input[i] == compare { pos = i; break; }
-    }
+fn parse_disc(name: &str) -> Option<(String, u32)> {
+    let captures = DISC_REGEX.captures(name.trim())?;
+    let number: u32 = captures[2].parse().ok()?;
 
-    pos
+    if number == 0 { return None; }
+    Some((remove_whitespace(&captures[1]), number))
 }
 
 //////////////////////////////////////////////////////////////////////////////////////
-// Find last position of the comparison character
+// Count discs of a multi-disc set from the file system
+//
+// Disc folders are counted from the folders next to the disc folder of the file, and
+// numbers like "101" from the audio files in the same folder.
 //
 // Inputs
-// input   - input string
-// compare - comparison character
+// path - path to audio file
 //
-// Return: Last position of the found character
+// Return: Highest disc number found, or 0 if there is none
 //////////////////////////////////////////////////////////////////////////////////////
-fn find_last_char(input: &[u8], compare: u8) -> usize {
-    let mut pos: usize = 0;
+fn count_discs(path: &str) -> u32 {
+    let folder = match Path::new(path).parent() {
+        Some(folder) if !folder.as_os_str().is_empty() => folder,
+        _ => Path::new("."),
+    };
 
-    for i in 0..input.len() {
-        if input[i] == compare { pos = i; }
+    let folder_disc = folder.file_name().and_then(|name| parse_disc(&name.to_string_lossy()));
+    let search = match (&folder_disc, folder.parent()) {
+        (Some(_), Some(parent)) if !parent.as_os_str().is_empty() => parent,
+        (Some(_), _) => Path::new("."),
+        (None, _) => folder,
+    };
+
+    if folder_disc.is_none() && !uses_disc_numbers(path) { return 0; }
+
+    let entries = match fs::read_dir(search) {
+        Ok(entries) => entries,
+        Err(_err) => return 0,
+    };
+
+    let mut highest: u32 = 0;
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        match &folder_disc {
+            // Only folders of the same album are counted, like "Album (Disc 1)" and "Album (Disc 2)", or "CD1" and "CD2"
+            Some((album, _)) => {
+                if let Some((other, number)) = parse_disc(&name).filter(|_| entry.path().is_dir()) {
+                    if other.to_lowercase() == album.to_lowercase() { highest = highest.max(number); }
+                }
+            }
+            None => if is_audio_file(&entry.path()) { highest = highest.max(TrackInfo::parse(&name).disc); },
+        }
     }
 
-    pos
+    highest
 }
 
 //////////////////////////////////////////////////////////////////////////////////////
-// Get nmber of given characters in a string
+// Check if three digit numbers of the audio files in the folder are disc and track numbers
 //
 // Inputs
-// input    - source string
-// compare  - comparison character
+// path - path to audio file
 //
-// Return: Number of items found in string
+// Return: true if every numbered file has a number like "101", and the numbers start from
+//         track 1 again on more than one disc, false if not or the folder can't be read
 //////////////////////////////////////////////////////////////////////////////////////
-fn get_char_count(input: &[u8], compare: u8) -> usize {
-    let mut count: usize = 0;
+fn uses_disc_numbers(path: &str) -> bool {
+    let folder = match Path::new(path).parent() {
+        Some(folder) if !folder.as_os_str().is_empty() => folder,
+        _ => Path::new("."),
+    };
+
+    let entries = match fs::read_dir(folder) {
+        Ok(entries) => entries,
+        Err(_err) => return false,
+    };
+
+    let mut numbers: Vec<(u32, u32)> = Vec::new();
 
-    for i in 0..input.len() {
-        if input[i] == compare { count += 1; }
+    for entry in entries.flatten().filter(|entry| is_audio_file(&entry.path())) {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.trim_start().starts_with(|c: char| c.is_ascii_digit()) { continue; }
+
+        let track = TrackInfo::parse(&name);
+        if track.disc == 0 { return false; }
+        numbers.push((track.disc, track.track));
     }
 
-    count
+    // Tracks 101 to 150 of an audiobook are a single run, while "101", "102", "201" and "202" start again
+    let mut discs: Vec<u32> = numbers.iter().map(|(disc, _track)| *disc).collect();
+    discs.sort();
+    discs.dedup();
+    discs.len() > 1 && discs.iter().all(|disc| numbers.contains(&(*disc, 1)))
 }
 
 //////////////////////////////////////////////////////////////////////////////////////
-// Remove whitespace from string with loops
+// Count tracks of the album from the audio files in the same folder
 //
 // Inputs
-// input - string to be cleaned
+// path - path to audio file
 //
-// Return: cleaned string
+// Return: Number of audio files in the folder, or of the same disc with numbers like "101", 0 if the folder can't be read
 //////////////////////////////////////////////////////////////////////////////////////
-fn remove_whitespace(input: &str) -> String {
-    let mut modified: String = format!("{}", input);
-    let mut start: usize = 0;
-    let mut end: usize = input.len();
-    let comparison: &[u8] = input.as_bytes();
-
-    for i in 0..comparison.len() {
-        if comparison[i] != b' ' && comparison[i] != b'-' && comparison[i] != b'_' && comparison[i] != b'\n' && comparison[i] != b'\t' && comparison[i] != b'/' { start = i; break; }
-    }
+fn count_tracks(path: &str) -> u32 {
+    let folder = match Path::new(path).parent() {
+        Some(folder) if !folder.as_os_str().is_empty() => folder,
+        _ => Path::new("."),
+    };
 
-    let mut j = comparison.len() - 1;
-    let mut k: i32 = j as i32; 
+    let disc = if uses_disc_numbers(path) { TrackInfo::parse(path).disc } else { 0 };
 
-    while k >= 0 {
-        if comparison[j] != b' ' && comparison[j] != b'-' && comparison[j] != b'_' && comparison[j] != b'\n' && comparison[j] != b'\t' && comparison[j] != b'/' { end = j + 1; break; }
-        j -= 1;
-        k -= 1;
+    match fs::read_dir(folder) {
+        Ok(entries) => entries.flatten().filter(|entry| {
+            is_audio_file(&entry.path()) && (disc == 0 || TrackInfo::parse(&entry.file_name().to_string_lossy()).disc == disc)
+        }).count() as u32,
+        Err(_err) => 0,
     }
-
-    modified.replace_range(end..modified.len(), "");
-    modified.replace_range(0..start, "");
-
-    modified
 }
 
 //////////////////////////////////////////////////////////////////////////////////////
-// ID3 tag struct handlers
+// Convert genre frame text to genre names
+//
+// ID3v2.3 refers to ID3v1 genres as "(17)", optionally followed by a refinement text,
+// and "((" starts a name beginning with "(". ID3v2.4 uses bare numbers and separates
+// several genres with a zero byte.
+//
+// Inputs
+// input - genre frame text, or genres separated with " / " as given from the commandline
+//
+// Return: genre names, several genres separated with " / "
 //////////////////////////////////////////////////////////////////////////////////////
-impl ID3TagInfo {
-    //////////////////////////////////////////////////////////////////////////////////////
-    // Read tag data from path
-    //
-    // Input
-    // path - Path to audio file
-    //
-    // Return: ID3TagInfo Struct with read data
-    //////////////////////////////////////////////////////////////////////////////////////
-    pub fn read(path: &str) -> ID3TagInfo {
-        let mut _artist : String = handle_tag_string(path, "artist");
-        let mut _title  : String = handle_tag_string(path, "title");
-        let mut _album  : String = handle_tag_string(path, "album");
-        let mut _year   : String = handle_tag_string(path, "year");
-        let mut _track  : String = handle_tag_string(path, "track");
+fn parse_genre(input: &str) -> String {
+    let mut names: Vec<String> = Vec::new();
 
-        if _year  == "empty" { _year = format!("0")
-        } else if ! _year.parse::<i32>().is_ok() { _year = format!("0"); }
+    for value in input.split('\0').flat_map(|value| value.split(" / ")).map(|value| value.trim()).filter(|value| !value.is_empty()) {
+        let mut rest: &str = value;
+        let mut references: Vec<String> = Vec::new();
 
-        if _track == "empty" { _track = format!("0")
-        } else if ! _track.parse::<i32>().is_ok() { _track = format!("0"); }
+        while rest.starts_with('(') && !rest.starts_with("((") {
+            let end = match rest.find(')') {
+                Some(end) => end,
+                None => break,
+            };
 
-        ID3TagInfo { artist: _artist, title: _title, album: _album, track: _track.parse().unwrap(), year: _year.parse().unwrap() }
-    }
+            let reference = &rest[1..end];
+            references.push(if reference == "RX" { String::from("Remix")
+            } else if reference == "CR" { String::from("Cover")
+            } else {
+                reference.parse::<usize>().ok().and_then(id3v1::genre_name).map(String::from).unwrap_or(format!("({})", reference))
+            });
+            rest = &rest[end + 1..];
+        }
 
-    //////////////////////////////////////////////////////////////////////////////////////
-    // Parse tag data for the audio file
-    //
-    // Inputs
-    // input - input string in format of "ARTIST - YEAR - ALBUM / TRACK - SONGNAME"
-    //
-    // Return: ID3TagInfo Struct with parsed data
-    //////////////////////////////////////////////////////////////////////////////////////
-    pub fn parse(input: &str) -> ID3TagInfo {
-        let pos = find_last_char(input.as_bytes(), b'/');
+        // "((" escapes a name starting with "("
+        let rest = if rest.starts_with("((") { &rest[1..] } else { rest }.trim();
 
-        if pos > 0 {
-            let atag: AlbumInfo = AlbumInfo::parse(input);
-            let ttag: TrackInfo = TrackInfo::parse(input);
-            ID3TagInfo { artist: atag.artist, title: ttag.title, album: atag.album, track: ttag.track, year: atag.year }
+        if rest.is_empty() {
+            names.extend(references);
+        } else if rest.chars().all(|c| c.is_ascii_digit()) {
+            names.push(rest.parse::<usize>().ok().and_then(id3v1::genre_name).map(String::from).unwrap_or(String::from(rest)));
         } else {
-            ID3TagInfo { artist: format!("empty"), title: format!("empty"), album: format!("empty"), track: 0, year: 0 }
+            // Refinement text is more specific than the referred genres
+            names.push(String::from(rest));
         }
     }
 
-    //////////////////////////////////////////////////////////////////////////////////////
-    // Force tagdata handlers
-    //
-    // Inputs
-    // _artist  - Artist name
-    // _year    - Release year
-    // _album   - Album name
-    // _track   - Track ID
-    // _title   - Title name of the track
-    //////////////////////////////////////////////////////////////////////////////////////
-    pub fn force(_artist: &str, _year: &str, _album: &str, _track: &str, _title: &str) -> ID3TagInfo {
-        ID3TagInfo { artist: format!("{}", _artist), title: format!("{}", _title), album: format!("{}", _album), track: _track.parse().unwrap(), year: _year.parse().unwrap() }
-    }
-}
-
-//////////////////////////////////////////////////////////////////////////////////////
-// Print out read ID3 tag info
-//////////////////////////////////////////////////////////////////////////////////////
-fn print_tag(info: &ID3TagInfo, path: &str) {
-    print!("\"{}\" '{}' - {} - '{}' : {} - '{}'\n", path, info.artist, info.year, info.album, info.track, info.title);
+    names.join(" / ")
 }
 
 //////////////////////////////////////////////////////////////////////////////////////
-// Remove tags from given audio file
+// Read genre alias map
+//
+// Each line is "ALIAS = GENRE", empty lines and lines starting with '#' are skipped.
 //
 // Inputs
-// path - Path to audio file
-// v    - verbose status
+// path - path to map file
+//
+// Return: list of aliases in lower case and their genres, or Err with the reason
 //////////////////////////////////////////////////////////////////////////////////////
-fn remove_tag(path: &str, info: &ID3TagInfo, v: &str) {
-    if is_empty(info) {
-        if v == "loud" || v == "verbose" { println!("No need to remove, item is already empty! '{}'", path); }
-    } else {
-        let do_steps = || -> Result<(), Box<dyn std::error::Error>> {
-            Tag::remove_from_path(path)?;
-            Ok(())
-        };
+fn read_genre_map(path: &str) -> Result<Vec<(String, String)>, String> {
+    let content = fs::read_to_string(path).map_err(|err| format!("Can't read genre map '{}': {}", path, err))?;
+    let mut map: Vec<(String, String)> = Vec::new();
 
-        if let Err(_err) = do_steps() {
-            if v == "loud" { println!("No tags found in '{}'", path); }
-        } else {
-            if v != "silent" && v != "entry" { println!("Removed tags from '{}'", path); }
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') { continue; }
+
+        match line.split_once('=') {
+            Some((alias, genre)) if !alias.trim().is_empty() && !genre.trim().is_empty() => {
+                map.push((alias.trim().to_lowercase(), String::from(genre.trim())));
+            }
+            _ => return Err(format!("Invalid line {} in genre map '{}', expected \"ALIAS = GENRE\"", number + 1, path)),
         }
     }
+
+    Ok(map)
 }
 
 //////////////////////////////////////////////////////////////////////////////////////
-// Compare two tag handlers
+// Replace genre aliases with the genres they map to
 //
 // Inputs
-// tag  - first tag
-// orig - second tag
+// genre - genre names, several genres separated with " / "
+// map   - aliases in lower case and their genres
 //
-// Return: Number of items that match
+// Return: mapped genre names, unknown names are kept as they are
 //////////////////////////////////////////////////////////////////////////////////////
-fn compare_tags(tag: &ID3TagInfo, orig: &ID3TagInfo) -> u8 {
-    let mut count: u8 = 0;
-
-    if tag.artist == orig.artist { count += 1; }
-    if tag.title  == orig.title  { count += 1; }
-    if tag.album  == orig.album  { count += 1; }
-    if tag.track  == orig.track  { count += 1; }
-    if tag.year   == orig.year   { count += 1; }
+fn normalize_genre(genre: &str, map: &[(String, String)]) -> String {
+    if genre == "empty" { return String::from(genre); }
 
-    count
+    genre.split(" / ").map(|name| {
+        map.iter().find(|(alias, _)| *alias == name.trim().to_lowercase()).map(|(_, mapped)| mapped.as_str()).unwrap_or(name)
+    }).collect::<Vec<&str>>().join(" / ")
 }
 
 //////////////////////////////////////////////////////////////////////////////////////
-// Check that tag items are correct
+// Read wanted data from ID3 tag
 //
 // Inputs
-// tag  - Input tag struct
+// tag - tag read from the audio file
+// src - type of wanted value
 //
-// Return: true if any of the items are incorrect
+// Return: wanted value in string, or "empty" if the tag doesn't have it
 //////////////////////////////////////////////////////////////////////////////////////
-fn is_empty(tag: &ID3TagInfo) -> bool {
-    let mut error: i16 = 0;
-
-    if tag.artist == "empty" { error += 1; }
-    if tag.title  == "empty" { error += 1; }
-    if tag.album  == "empty" { error += 1; }
-    if tag.track  <  1       { error += 1; }
-    if tag.year   <  1900    { error += 1; }
-
-    if error > 4 { true
-    } else { false }
+fn handle_tag_string(tag: &Tag, src: &str) -> String {
+    // Values are taken as they are, so writing them back doesn't change them
+    let value: Option<String> = if src == "album" { tag.album().map(String::from)
+    } else if src == "artist" { tag.artist().map(String::from)
+    } else if src == "title"  { tag.title().map(String::from)
+    } else if src == "genre"  { tag.genre().map(parse_genre)
+    } else if src == "album_artist" { tag.album_artist().map(String::from)
+    } else if src == "compilation"  { tag.get("TCMP").and_then(|frame| frame.content().text()).map(String::from)
+    } else if src == "year" { tag.year().or_else(|| tag.date_recorded().map(|date| date.year)).map(|year| year.to_string())
+    } else if src == "track" { tag.track().map(|track| track.to_string())
+    } else if src == "track_total" { tag.total_tracks().map(|total| total.to_string())
+    } else if src == "disc" { tag.disc().map(|disc| disc.to_string())
+    } else if src == "disc_total" { tag.total_discs().map(|total| total.to_string())
+    } else { None };
+
+    value.filter(|value| !value.is_empty()).unwrap_or(format!("empty"))
 }
 
 //////////////////////////////////////////////////////////////////////////////////////
-// Get number of empty items
+// Read tag from audio file, falling back to ID3v1 if the file has no ID3v2 tag, or to RIFF INFO list with WAV files.
+// FLAC and Ogg Vorbis comments, and MP4 item atoms are converted to ID3 frames, so they are handled the same way
+//
+// Inputs
+// path   - path to audio file
+// format - container format as in detect_format
+//
+// Return: Tag and the version it was read from, or None if no tag is found or it can't be read
 //////////////////////////////////////////////////////////////////////////////////////
-fn _empty_count(tag: &ID3TagInfo) -> i16 {
-    let mut error: i16 = 0;
-
-    if        tag.artist == "empty" { error += 1;
-    } else if tag.title  == "empty" { error += 1;
-    } else if tag.album  == "empty" { error += 1;
-    } else if tag.track  <  1       { error += 1;
-    } else if tag.year   <  1900    { error += 1;
+fn read_tag(path: &str, format: &str) -> Option<(Tag, String)> {
+    match read_tag_checked(path, format) {
+        Ok(found) => found,
+        // File with a broken ID3v2 tag may still have a readable ID3v1 tag
+        Err(_err) => id3::v1::Tag::read_from_path(path).ok().map(|tag| (Tag::from(tag), String::from("ID3v1"))),
     }
-
-    error
 }
 
 //////////////////////////////////////////////////////////////////////////////////////
-// Initialize tag handler with previously read data
+// Read tag from audio file as read_tag does, telling apart missing tags and tags that can't be read
 //
 // Inputs
-// source   - Tag info struct
+// path   - path to audio file
+// format - container format as in detect_format
 //
-// Return: Newly initialized Tag handler
+// Return: Tag and the version it was read from, None if the file has no tag, or error if the tag
+//         is broken, so it isn't replaced by a new tag when writing
 //////////////////////////////////////////////////////////////////////////////////////
-fn get_tag(source: &ID3TagInfo) -> Tag {
-    let mut target = Tag::new();
+fn read_tag_checked(path: &str, format: &str) -> Result<Option<(Tag, String)>, Box<dyn std::error::Error>> {
+    // Files without comments or item atoms are read as empty tags
+    if format == "flac" {
+        return Ok(Some((flac::read(path)?, String::from("Vorbis comment"))));
+    } else if format == "ogg" {
+        return Ok(Some((ogg::read(path)?, String::from("Vorbis comment"))));
+    } else if format == "mp4" {
+        return Ok(Some((mp4::read(path)?, String::from("MP4 ilst"))));
+    }
+
+    // ID3 chunks of WAV and AIFF files are found by the same call
+    match Tag::read_from_path(path) {
+        Ok(tag) => {
+            let version = format!("{}", tag.version());
+            return Ok(Some((tag, version)));
+        }
+        Err(err) if matches!(err.kind, id3::ErrorKind::NoTag) => {}
+        Err(err) => return Err(err.into()),
+    }
 
-    target.set_album(&source.album);
-    target.set_title(&source.title);
-    target.set_artist(&source.artist);
-    target.set_track(source.track);
-    target.set_year(source.year);
+    if format == "wav" {
+        return Ok(riff::read_info(path).ok().map(|tag| (tag, String::from("RIFF INFO"))));
+    }
 
-    target
+    Ok(id3::v1::Tag::read_from_path(path).ok().map(|tag| (Tag::from(tag), String::from("ID3v1"))))
 }
 
 //////////////////////////////////////////////////////////////////////////////////////
-// Write tag-data to audio file
+// Find first position of the comparison character
 //
 // Inputs
-// path - path to audio file
-// tag  - previously parsed tag data
-// v    - verbose status
-//////////////////////////////////////////////////////////////////////////////////////
-fn write_tags(path: &str, tag: &ID3TagInfo, orig: &ID3TagInfo, v: &str) {
-    let error = is_empty(tag);
+// input   - input string
+// compare - comparison character
+//
+// Return: First byte position of the found character, or 0 if not found
+//////////////////////////////////////////////////////////////////////////////////////
+fn find_first_char(input: &str, compare: char) -> usize {
+    input.find(compare).unwrap_or(0)
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Find last position of the comparison character
+//
+// Inputs
+// input   - input string
+// compare - comparison character
+//
+// Return: Last byte position of the found character, or 0 if not found
+//////////////////////////////////////////////////////////////////////////////////////
+fn find_last_char(input: &str, compare: char) -> usize {
+    input.rfind(compare).unwrap_or(0)
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Get nmber of given characters in a string
+//
+// Inputs
+// input    - source string
+// compare  - comparison character
+//
+// Return: Number of items found in string
+//////////////////////////////////////////////////////////////////////////////////////
+fn get_char_count(input: &str, compare: char) -> usize {
+    input.matches(compare).count()
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Remove whitespace and separator characters from both ends of a string
+//
+// Inputs
+// input - string to be cleaned
+//
+// Return: cleaned string
+//////////////////////////////////////////////////////////////////////////////////////
+fn remove_whitespace(input: &str) -> String {
+    String::from(input.trim_matches(|c: char| c == ' ' || c == '-' || c == '_' || c == '\n' || c == '\t' || c == '/'))
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// ID3 tag struct handlers
+//////////////////////////////////////////////////////////////////////////////////////
+impl ID3TagInfo {
+    //////////////////////////////////////////////////////////////////////////////////////
+    // Read tag data from path
+    //
+    // Input
+    // path   - Path to audio file
+    // format - container format as in detect_format
+    //
+    // Return: ID3TagInfo Struct with read data
+    //////////////////////////////////////////////////////////////////////////////////////
+    pub fn read(path: &str, format: &str) -> ID3TagInfo {
+        match read_tag(path, format) {
+            Some((tag, source)) => ID3TagInfo::from_tag(&tag, &source),
+            None => ID3TagInfo::empty(),
+        }
+    }
+
+    //////////////////////////////////////////////////////////////////////////////////////
+    // Get tag data from a tag already read from the file
+    //
+    // Inputs
+    // tag    - tag read from the audio file
+    // source - version the tag was read from
+    //
+    // Return: ID3TagInfo Struct with the tag data
+    //////////////////////////////////////////////////////////////////////////////////////
+    pub fn from_tag(tag: &Tag, source: &str) -> ID3TagInfo {
+        let number = |src: &str| -> u32 { handle_tag_string(tag, src).parse().unwrap_or(0) };
+
+        ID3TagInfo { artist: handle_tag_string(tag, "artist"), title: handle_tag_string(tag, "title"), album: handle_tag_string(tag, "album"), track: number("track"),
+                     year: handle_tag_string(tag, "year").parse().unwrap_or(0), track_total: number("track_total"), disc: number("disc"), disc_total: number("disc_total"),
+                     genre: handle_tag_string(tag, "genre"), album_artist: handle_tag_string(tag, "album_artist"), compilation: handle_tag_string(tag, "compilation") == "1",
+                     extra: tag.extended_texts().map(|text| (text.description.clone(), text.value.clone())).collect(), source: String::from(source) }
+    }
+
+    //////////////////////////////////////////////////////////////////////////////////////
+    // Initialize empty tag data
+    //
+    // Return: ID3TagInfo Struct with all items empty
+    //////////////////////////////////////////////////////////////////////////////////////
+    pub fn empty() -> ID3TagInfo {
+        ID3TagInfo { artist: String::from("empty"), title: String::from("empty"), album: String::from("empty"), track: 0, year: 0, track_total: 0, disc: 0, disc_total: 0, genre: String::from("empty"), album_artist: String::from("empty"), compilation: false, extra: Vec::new(), source: String::from("empty") }
+    }
+
+    //////////////////////////////////////////////////////////////////////////////////////
+    // Set tag item by its name
+    //
+    // Inputs
+    // name  - item name: artist, album, title, genre, albumartist, year, track or disc
+    // value - item value as string
+    //
+    // Return: false if the item name is unknown
+    //////////////////////////////////////////////////////////////////////////////////////
+    pub fn set_field(&mut self, name: &str, value: &str) -> bool {
+        let current_date = chrono::Utc::now();
+        let value = value.trim();
+
+        if        name == "artist" { self.artist = String::from(value);
+        } else if name == "album"  { self.album  = String::from(value);
+        } else if name == "title"  { self.title  = String::from(value);
+        } else if name == "genre"  { self.genre  = parse_genre(value);
+        } else if name == "albumartist" { self.album_artist = String::from(value);
+        } else if name == "year"   { self.year   = verify_number(value, 1800, current_date.year()).parse().unwrap();
+        } else if name == "track" {
+            // Track can be given with the total, like "3/12"
+            let (track, total) = value.split_once('/').unwrap_or((value, "0"));
+            self.track = verify_number(track, 1, MAX_TRACK).parse().unwrap();
+            self.track_total = verify_number(total, 1, MAX_TRACK).parse().unwrap();
+        } else if name == "disc" {
+            // Disc can be given with the total, like "2/3"
+            let (disc, total) = value.split_once('/').unwrap_or((value, "0"));
+            self.disc = verify_number(disc, 1, 99).parse().unwrap();
+            self.disc_total = verify_number(total, 1, 99).parse().unwrap();
+        } else { return false; }
+
+        true
+    }
+
+    //////////////////////////////////////////////////////////////////////////////////////
+    // Get tag item by its name
+    //
+    // Inputs
+    // name  - item name: artist, album, title, genre, albumartist, year, track, disc or a TXXX description
+    //
+    // Return: item value as string, or None if the item is empty or unknown
+    //////////////////////////////////////////////////////////////////////////////////////
+    pub fn get_field(&self, name: &str) -> Option<String> {
+        let value: String;
+
+        if        name == "artist" { value = self.artist.clone();
+        } else if name == "album"  { value = self.album.clone();
+        } else if name == "title"  { value = self.title.clone();
+        } else if name == "genre"  { value = self.genre.clone();
+        } else if name == "albumartist" { value = if self.album_artist != "empty" { self.album_artist.clone() } else { self.artist.clone() };
+        } else if name == "year"   { value = if self.year  > 0 { self.year.to_string()  } else { String::from("empty") };
+        } else if name == "track"  { value = if self.track > 0 { self.track.to_string() } else { String::from("empty") };
+        } else if name == "disc"   { value = if self.disc  > 0 { self.disc.to_string()  } else { String::from("empty") };
+        } else {
+            value = self.extra.iter().find(|(description, _)| description.to_lowercase() == name).map(|(_, text)| text.clone()).unwrap_or(String::from("empty"));
+        }
+
+        if value == "empty" || value.is_empty() { None } else { Some(value) }
+    }
+
+    //////////////////////////////////////////////////////////////////////////////////////
+    // Replace genre aliases, using the genre of the original tag if no genre is given
+    //
+    // Inputs
+    // orig - tag data read from the file
+    // map  - aliases in lower case and their genres, nothing is done if empty
+    //////////////////////////////////////////////////////////////////////////////////////
+    pub fn map_genre(&mut self, orig: &ID3TagInfo, map: &[(String, String)]) {
+        if map.is_empty() { return; }
+        if self.genre == "empty" { self.genre = orig.genre.clone(); }
+
+        self.genre = normalize_genre(&self.genre, map);
+    }
+
+    //////////////////////////////////////////////////////////////////////////////////////
+    // Parse tag data for the audio file
+    //
+    // Inputs
+    // input   - input string in format of "ARTIST - YEAR - ALBUM / TRACK - SONGNAME"
+    // pattern - user given path template, or "empty" to guess the layout
+    //
+    // Return: ID3TagInfo Struct with parsed data
+    //////////////////////////////////////////////////////////////////////////////////////
+    pub fn parse(input: &str, pattern: &str) -> ID3TagInfo {
+        let pos = find_last_char(input, '/');
+
+        let mut info = if pattern != "empty" {
+            ID3TagInfo::parse_pattern(input, pattern)
+        } else if pos > 0 {
+            let atag: AlbumInfo = AlbumInfo::parse(input);
+            let ttag: TrackInfo = TrackInfo::parse(input);
+
+            // Numbers like "101" are plain track numbers, if the folder has other numbering too
+            let (track, disc) = if ttag.disc > 0 && !uses_disc_numbers(input) { (ttag.disc * 100 + ttag.track, 0) } else { (ttag.track, ttag.disc) };
+            let disc = if atag.disc > 0 { atag.disc } else { disc };
+            ID3TagInfo { artist: atag.artist, title: ttag.title, album: atag.album, track, year: atag.year, track_total: 0, disc, disc_total: 0, genre: String::from("empty"), album_artist: String::from("empty"), compilation: false, extra: Vec::new(), source: String::from("empty") }
+        } else {
+            ID3TagInfo::empty()
+        };
+
+        if info.track > 0 && info.track_total == 0 { info.track_total = count_tracks(input); }
+        if info.disc > 0 && info.disc_total == 0 { info.disc_total = count_discs(input); }
+        info
+    }
+
+    //////////////////////////////////////////////////////////////////////////////////////
+    // Parse tag data of a compilation, where the folder names the album artist and each file
+    // name has the artist of the track, like "Various Artists - 2003 - Hits/03 - Artist - Title"
+    //
+    // Inputs
+    // input - input string in format of "ALBUM ARTIST - YEAR - ALBUM / TRACK - ARTIST - SONGNAME"
+    //
+    // Return: ID3TagInfo Struct with parsed data, artist is left empty if the file name has only the title
+    //////////////////////////////////////////////////////////////////////////////////////
+    pub fn parse_compilation(input: &str) -> ID3TagInfo {
+        let mut info = ID3TagInfo::parse(input, "empty");
+        if find_last_char(input, '/') == 0 { return info; }
+
+        info.album_artist = if info.artist == "empty" || info.artist.is_empty() { String::from("Various Artists") } else { info.artist.clone() };
+        info.compilation = true;
+
+        match info.title.split_once(" - ") {
+            Some((artist, title)) => {
+                info.artist = remove_whitespace(artist);
+                info.title = remove_whitespace(title);
+            }
+            None => info.artist = String::from("empty"),
+        }
+
+        info
+    }
+
+    //////////////////////////////////////////////////////////////////////////////////////
+    // Parse tag data by matching a path template, e.g. "%artist%/%year% - %album%/%track%. %title%"
+    //
+    // Inputs
+    // input   - path to audio file
+    // pattern - path template, one folder level per '/'
+    //
+    // Return: ID3TagInfo Struct with matched data, fields not in the template are left empty
+    //////////////////////////////////////////////////////////////////////////////////////
+    pub fn parse_pattern(input: &str, pattern: &str) -> ID3TagInfo {
+        let mut info = ID3TagInfo::empty();
+        let tokens: Vec<PatternToken> = parse_pattern_tokens(pattern);
+        let mut fields: Vec<(String, String)> = Vec::new();
+        let source = get_pattern_source(input, pattern.matches('/').count() + 1);
+
+        if !match_pattern(&tokens, &source, &mut fields) { return info; }
+
+        for (name, value) in fields {
+            info.set_field(&name, &value);
+        }
+
+        info
+    }
+
+    //////////////////////////////////////////////////////////////////////////////////////
+    // Parse tag data with a regular expression using named capture groups
+    //
+    // Inputs
+    // input - full path to audio file
+    // regex - expression with groups like (?P<artist>...), unknown names become TXXX frames
+    //
+    // Return: ID3TagInfo Struct with captured data, or empty struct if there's no match
+    //////////////////////////////////////////////////////////////////////////////////////
+    pub fn parse_regex(input: &str, regex: &Regex) -> ID3TagInfo {
+        let mut info = ID3TagInfo::empty();
+
+        if let Some(captures) = regex.captures(input) {
+            for name in regex.capture_names().flatten() {
+                if let Some(value) = captures.name(name) {
+                    if !info.set_field(&name.to_lowercase(), value.as_str()) {
+                        info.extra.push((String::from(name), String::from(value.as_str().trim())));
+                    }
+                }
+            }
+        }
+
+        info
+    }
+
+    //////////////////////////////////////////////////////////////////////////////////////
+    // Force tagdata handlers
+    //
+    // Inputs
+    // _artist  - Artist name
+    // _year    - Release year
+    // _album   - Album name
+    // _track   - Track ID
+    // _title   - Title name of the track
+    //
+    // Return: ID3TagInfo Struct, year and track must be validated beforehand with validate_field
+    //////////////////////////////////////////////////////////////////////////////////////
+    pub fn force(_artist: &str, _year: &str, _album: &str, _track: &str, _title: &str) -> ID3TagInfo {
+        let mut info = ID3TagInfo::empty();
+
+        info.set_field("artist", _artist);
+        info.set_field("year",   _year);
+        info.set_field("album",  _album);
+        info.set_field("track",  _track);
+        info.set_field("title",  _title);
+
+        info
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Print out read ID3 tag info
+//////////////////////////////////////////////////////////////////////////////////////
+fn print_tag(info: &ID3TagInfo, path: &str, format: &str) {
+    print!("\"{}\" '{}' - {} - '{}' : ", path, info.artist, info.year, info.album);
+
+    if info.track_total > 0 { print!("{}/{}", info.track, info.track_total); } else { print!("{}", info.track); }
+    print!(" - '{}'", info.title);
+
+    if info.genre != "empty" { print!(" - '{}'", info.genre); }
+
+    let mut notes: Vec<String> = Vec::new();
+    if info.disc > 0 && info.disc_total > 0 { notes.push(format!("disc {}/{}", info.disc, info.disc_total));
+    } else if info.disc > 0 {                 notes.push(format!("disc {}", info.disc)); }
+    if info.album_artist != "empty" {         notes.push(format!("album artist '{}'", info.album_artist)); }
+    if info.compilation {                     notes.push(String::from("compilation")); }
+
+    if !notes.is_empty() { print!(" {{{}}}", notes.join(", ")); }
+
+    print!(" [{}]", format_name(format));
+
+    if info.source != "empty" { print!(" ({})", info.source); }
+    println!();
+}
+
+// Names of ID3v2 text encoding bytes
+const ENCODING_NAMES: [&str; 4] = ["Latin-1", "UTF-16", "UTF-16BE", "UTF-8"];
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Print out every frame of the tag, with the tag layout when it's an ID3v2 tag at the start of the file
+//
+// Inputs
+// path   - path to audio file
+// format - container format as in detect_format
+//////////////////////////////////////////////////////////////////////////////////////
+fn print_frames(path: &str, format: &str) {
+    let (tag, source) = match read_tag(path, format) {
+        Some(found) => found,
+        None => { println!("    No tag"); return; }
+    };
+
+    // Tags converted from other containers have no ID3 layout of their own
+    let raw = if source.starts_with("ID3v2") { read_raw_tag(path) } else { None };
+
+    match &raw {
+        Some(raw) => {
+            print!("    ID3v2.{}, {} bytes", raw.version, raw.size);
+            if let Some(padding) = raw.padding { print!(", {} bytes padding", padding); }
+            println!();
+        }
+        None if source.starts_with("ID3v2") => println!("    {} (chunk)", source),
+        None => println!("    {}, shown as ID3 frames", source),
+    }
+
+    let mut seen: Vec<&str> = Vec::new();
+
+    for frame in tag.frames() {
+        let id = frame.id();
+        let nth = seen.iter().filter(|other| **other == id).count();
+        seen.push(id);
+
+        // Frames are matched to the stored ones by their order within the same ID
+        let mut details: Vec<String> = Vec::new();
+        if let Some((_, size, first)) = raw.iter().flat_map(|raw| raw.frames.iter()).filter(|(other, _, _)| other == id).nth(nth) {
+            if has_encoding(id) { details.push(String::from(ENCODING_NAMES.get(*first as usize).copied().unwrap_or("unknown encoding"))); }
+            details.push(format!("{} bytes", size));
+        }
+
+        let details = if details.is_empty() { String::new() } else { format!(" ({})", details.join(", ")) };
+        println!("    {}{}: {}", id, details, describe_content(frame.content()));
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Describe frame content for printing
+//
+// Inputs
+// content - frame content
+//
+// Return: Content as text, binary data is shown only by its size
+//////////////////////////////////////////////////////////////////////////////////////
+fn describe_content(content: &Content) -> String {
+    if let Some(text) = content.extended_text() {
+        format!("'{}' = '{}'", text.description, text.value.replace('\0', "' / '"))
+    } else if let Some(comment) = content.comment() {
+        format!("[{}] '{}' = '{}'", comment.lang, comment.description, comment.text.replace('\0', "' / '"))
+    } else if let Some(lyrics) = content.lyrics() {
+        format!("[{}] '{}' = {} characters", lyrics.lang, lyrics.description, lyrics.text.chars().count())
+    } else if let Some(picture) = content.picture() {
+        format!("{} ({}), {}, {} bytes, '{}'", picture.picture_type, u8::from(picture.picture_type), picture.mime_type, picture.data.len(), picture.description)
+    } else if let Some(link) = content.extended_link() {
+        format!("'{}' = {}", link.description, link.link)
+    } else if let Some(values) = content.text_values() {
+        format!("'{}'", values.collect::<Vec<&str>>().join("' / '"))
+    } else if let Content::Unknown(unknown) = content {
+        format!("{} bytes of unknown data", unknown.data.len())
+    } else {
+        format!("{}", content)
+    }
+}
+
+// Columns of CSV and TSV output
+const RECORD_COLUMNS: [&str; 14] = ["path", "container", "source", "artist", "year", "album", "track", "title", "genre", "albumartist", "compilation", "disc", "disctotal", "tracktotal"];
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Print out header line of machine readable output
+//
+// Inputs
+// format - output format: json, csv or tsv
+//////////////////////////////////////////////////////////////////////////////////////
+fn print_record_header(format: &str) {
+    if format == "csv" { println!("{}", RECORD_COLUMNS.join(","));
+    } else if format == "tsv" { println!("{}", RECORD_COLUMNS.join("\t")); }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Print out read tag info as one machine readable record
+//
+// Inputs
+// info      - tag data read from the file
+// path      - path to audio file
+// container - container format as in detect_format
+// format    - output format: json (one object per line), csv or tsv
+//////////////////////////////////////////////////////////////////////////////////////
+fn print_record(info: &ID3TagInfo, path: &str, container: &str, format: &str) {
+    println!("{}", format_record(info, path, container, format));
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Format read tag info as one machine readable record
+//
+// Return: Record line, without the line break
+//////////////////////////////////////////////////////////////////////////////////////
+fn format_record(info: &ID3TagInfo, path: &str, container: &str, format: &str) -> String {
+    let text = |value: &str| -> Option<String> { if value == "empty" { None } else { Some(String::from(value)) } };
+    let number = |value: i64| -> Option<String> { if value > 0 { Some(format!("{}", value)) } else { None } };
+
+    // Missing items are null, not the "empty" used internally
+    let values: [(Option<String>, bool); 14] = [
+        (Some(String::from(path)), false), (Some(String::from(format_name(container))), false), (text(&info.source), false),
+        (text(&info.artist), false), (number(info.year as i64), true), (text(&info.album), false),
+        (number(info.track as i64), true), (text(&info.title), false), (text(&info.genre), false),
+        (text(&info.album_artist), false), (Some(format!("{}", info.compilation)), true),
+        (number(info.disc as i64), true), (number(info.disc_total as i64), true), (number(info.track_total as i64), true),
+    ];
+
+    if format == "json" {
+        let mut items: Vec<String> = values.iter().zip(RECORD_COLUMNS.iter()).map(|((value, numeric), name)| {
+            match value {
+                Some(value) if *numeric => format!("\"{}\":{}", name, value),
+                Some(value) => format!("\"{}\":{}", name, json_string(value)),
+                None => format!("\"{}\":null", name),
+            }
+        }).collect();
+
+        let extra: Vec<String> = info.extra.iter().map(|(description, value)| format!("{}:{}", json_string(description), json_string(value))).collect();
+        items.push(format!("\"extra\":{{{}}}", extra.join(",")));
+
+        format!("{{{}}}", items.join(","))
+    } else {
+        let fields: Vec<String> = values.iter().map(|(value, _)| {
+            let value = value.clone().unwrap_or_default();
+
+            if format == "tsv" {
+                value.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+            } else if value.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", value.replace('"', "\"\""))
+            } else {
+                value
+            }
+        }).collect();
+
+        fields.join(if format == "tsv" { "\t" } else { "," })
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Quote and escape string for JSON output
+//////////////////////////////////////////////////////////////////////////////////////
+fn json_string(value: &str) -> String {
+    let mut quoted = String::from("\"");
+
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Remove tags from given audio file
+//
+// Inputs
+// path   - Path to audio file
+// format - container format as in detect_format
+// which  - removed tags: v1, v2 or both, tags of FLAC, Ogg and MP4 files and RIFF INFO lists count as v2
+// v      - verbose status
+// dry    - only print out the tags that would be removed, without removing anything
+//
+// Return: Status of the removal
+//////////////////////////////////////////////////////////////////////////////////////
+fn remove_tag(path: &str, format: &str, which: &str, v: &str, dry: bool) -> FileStatus {
+    // Any frame counts, so tags holding only a genre, pictures or user defined texts are removed too
+    let has_v2 = match read_tag_checked(path, format) {
+        Ok(Some((tag, source))) => source != "ID3v1" && tag.frames().count() > 0,
+        Ok(None) => false,
+        Err(_err) => true,                                          // Broken tag is removed as well
+    };
+    let remove_v2 = (which == "v2" || which == "both") && has_v2;
+    let remove_v1 = (which == "v1" || which == "both") && id3v1::read_raw(path).is_some();
+
+    if !remove_v2 && !remove_v1 {
+        if v == "loud" || v == "verbose" { println!("No need to remove, item is already empty! '{}'", path); }
+        return FileStatus::Skipped;
+    }
+
+    let removed = if remove_v1 && remove_v2 { "ID3v1 and ID3v2 tags" } else if remove_v1 { "ID3v1 tag" } else { "tags" };
+
+    if dry {
+        if v != "silent" { println!("Would remove {} from '{}'", removed, path); }
+        FileStatus::Updated
+    } else {
+        let do_steps = || -> Result<(), Box<dyn std::error::Error>> {
+            if remove_v2 && format == "flac" { flac::remove(path)?;
+            } else if remove_v2 && format == "ogg" { ogg::remove(path)?;
+            } else if remove_v2 && format == "mp4" { mp4::remove(path)?;
+            } else if remove_v2 && (format == "wav" || format == "aiff") { riff::remove_tags(path)?;
+            } else if remove_v2 { Tag::remove_from_path(path)?; }
+            if remove_v1 { id3::v1::Tag::remove_from_path(path)?; }
+            Ok(())
+        };
+
+        if let Err(_err) = do_steps() {
+            if v == "loud" { println!("No tags found in '{}'", path); }
+            FileStatus::Failed
+        } else {
+            if v != "silent" && v != "entry" { println!("Removed {} from '{}'", removed, path); }
+            FileStatus::Updated
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Compare two tag handlers
+//
+// Inputs
+// tag  - first tag
+// orig - second tag
+//
+// Return: Number of items that match
+//////////////////////////////////////////////////////////////////////////////////////
+fn compare_tags(tag: &ID3TagInfo, orig: &ID3TagInfo) -> u8 {
+    let mut count: u8 = 0;
+
+    // Empty items are not written, so they always match
+    if tag.artist == "empty" || tag.artist == orig.artist { count += 1; }
+    if tag.title  == "empty" || tag.title  == orig.title  { count += 1; }
+    if tag.album  == "empty" || tag.album  == orig.album  { count += 1; }
+    if tag.track  == 0       || tag.track  == orig.track  { count += 1; }
+    if tag.year   == 0       || tag.year   == orig.year   { count += 1; }
+
+    count
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Check that track total, disc, genre, album artist, compilation flag and all user defined frames of the new tag are already in the original
+//
+// Inputs
+// tag  - new tag
+// orig - tag read from the file
+//
+// Return: true if nothing needs to be written
+//////////////////////////////////////////////////////////////////////////////////////
+fn compare_extra(tag: &ID3TagInfo, orig: &ID3TagInfo) -> bool {
+    (tag.track_total == 0 || tag.track_total == orig.track_total) && (tag.disc == 0 || (tag.disc == orig.disc && (tag.disc_total == 0 || tag.disc_total == orig.disc_total))) &&
+        (tag.genre == "empty" || tag.genre == orig.genre) && (tag.album_artist == "empty" || tag.album_artist == orig.album_artist) &&
+        (!tag.compilation || orig.compilation) && tag.extra.iter().all(|item| orig.extra.contains(item))
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Print out per item differences of two tag handlers
+//
+// Inputs
+// tag  - new tag
+// orig - tag read from the file
+// v    - verbose status, equal items are printed only when loud
+//////////////////////////////////////////////////////////////////////////////////////
+fn print_diff(tag: &ID3TagInfo, orig: &ID3TagInfo, v: &str) {
+    let number = |number: u32, total: u32| -> String { if total > 0 { format!("{}/{}", number, total) } else { format!("{}", number) } };
+    let text = |new: &str, old: &str| -> String { String::from(if new == "empty" { old } else { new }) };
+    let mut items: Vec<(String, String, String)> = vec![
+        (String::from("artist"), orig.artist.clone(),      text(&tag.artist, &orig.artist)),
+        (String::from("year"),   orig.year.to_string(),    if tag.year > 0 { tag.year } else { orig.year }.to_string()),
+        (String::from("album"),  orig.album.clone(),       text(&tag.album, &orig.album)),
+        (String::from("track"),  number(orig.track, orig.track_total), number(if tag.track > 0 { tag.track } else { orig.track }, if tag.track_total > 0 { tag.track_total } else { orig.track_total })),
+        (String::from("title"),  orig.title.clone(),       text(&tag.title, &orig.title)),
+    ];
+
+    if tag.disc > 0 {
+        let total = if tag.disc_total > 0 { tag.disc_total } else { orig.disc_total };
+        items.push((String::from("disc"), number(orig.disc, orig.disc_total), number(tag.disc, total)));
+    }
+
+    if tag.genre != "empty" {
+        items.push((String::from("genre"), orig.genre.clone(), tag.genre.clone()));
+    }
+
+    if tag.album_artist != "empty" {
+        items.push((String::from("album artist"), orig.album_artist.clone(), tag.album_artist.clone()));
+    }
+
+    if tag.compilation {
+        items.push((String::from("compilation"), String::from(if orig.compilation { "1" } else { "empty" }), String::from("1")));
+    }
+
+    for (description, value) in &tag.extra {
+        let old = orig.extra.iter().find(|(name, _)| name == description).map(|(_, old)| old.clone());
+        items.push((format!("TXXX:{}", description), old.unwrap_or(String::from("empty")), value.clone()));
+    }
+
+    for (name, before, after) in items {
+        if before != after {   println!("    {}: '{}' -> '{}'", name, before, after);
+        } else if v == "loud" { println!("    {}: '{}' (unchanged)", name, before); }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Check that tag items are correct
+//
+// Inputs
+// tag  - Input tag struct
+//
+// Return: true if any of the items are incorrect
+//////////////////////////////////////////////////////////////////////////////////////
+fn is_empty(tag: &ID3TagInfo) -> bool {
+    let mut error: i16 = 0;
+
+    if tag.artist == "empty" { error += 1; }
+    if tag.title  == "empty" { error += 1; }
+    if tag.album  == "empty" { error += 1; }
+    if tag.track  <  1       { error += 1; }
+    if tag.year   <  1900    { error += 1; }
+
+    if error > 4 { true
+    } else { false }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Get number of empty items
+//////////////////////////////////////////////////////////////////////////////////////
+fn _empty_count(tag: &ID3TagInfo) -> i16 {
+    let mut error: i16 = 0;
+
+    if        tag.artist == "empty" { error += 1;
+    } else if tag.title  == "empty" { error += 1;
+    } else if tag.album  == "empty" { error += 1;
+    } else if tag.track  <  1       { error += 1;
+    } else if tag.year   <  1900    { error += 1;
+    }
+
+    error
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// FrameReport struct handlers
+//////////////////////////////////////////////////////////////////////////////////////
+impl FrameReport {
+    pub fn new() -> FrameReport {
+        FrameReport { kept: Vec::new(), changed: Vec::new(), added: Vec::new() }
+    }
+
+    //////////////////////////////////////////////////////////////////////////////////////
+    // Mark frame as changed or added
+    //
+    // Inputs
+    // id      - frame ID
+    // existed - true if the frame was already in the tag
+    //////////////////////////////////////////////////////////////////////////////////////
+    pub fn mark(&mut self, id: &str, existed: bool) {
+        if existed { self.changed.push(String::from(id));
+        } else {     self.added.push(String::from(id)); }
+    }
+
+    //////////////////////////////////////////////////////////////////////////////////////
+    // Print out the report
+    //////////////////////////////////////////////////////////////////////////////////////
+    pub fn print(&self) {
+        println!("    -> kept:[{}] changed:[{}] added:[{}]", self.kept.join(", "), self.changed.join(", "), self.added.join(", "));
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Update existing tag handler with previously read data, leaving all other frames intact
+//
+// Inputs
+// target   - Tag handler read from the file, or an empty one
+// source   - Tag info struct
+//
+// Return: Report of the kept, changed and added frames
+//////////////////////////////////////////////////////////////////////////////////////
+fn update_tag(target: &mut Tag, source: &ID3TagInfo) -> FrameReport {
+    let mut report = FrameReport::new();
+    // Extended texts are reported by their description, as in TXXX:MOOD
+    let existing: Vec<String> = target.frames().map(|frame| match frame.content() {
+        Content::ExtendedText(text) => format!("TXXX:{}", text.description),
+        _ => String::from(frame.id()),
+    }).collect();
+
+    // Empty items are left as they are in the file
+    if source.album != "empty" && target.album() != Some(source.album.as_str()) {
+        report.mark("TALB", target.album().is_some());
+        target.set_album(&source.album);
+    }
+
+    if source.title != "empty" && target.title() != Some(source.title.as_str()) {
+        report.mark("TIT2", target.title().is_some());
+        target.set_title(&source.title);
+    }
+
+    if source.artist != "empty" && target.artist() != Some(source.artist.as_str()) {
+        report.mark("TPE1", target.artist().is_some());
+        target.set_artist(&source.artist);
+    }
+
+    // Total of tracks is kept if it isn't known
+    if source.track > 0 && (target.track() != Some(source.track) || (source.track_total > 0 && target.total_tracks() != Some(source.track_total))) {
+        report.mark("TRCK", target.get("TRCK").is_some());
+        target.set_track(source.track);
+        if source.track_total > 0 { target.set_total_tracks(source.track_total); }
+    }
+
+    // Year can be stored either as TYER or, by ID3v2.4 writers, as TDRC
+    let year = target.year().or_else(|| target.date_recorded().map(|date| date.year));
+
+    if source.year > 0 && year != Some(source.year) {
+        if target.get("TYER").is_none() && target.get("TDRC").is_some() {
+            report.mark("TDRC", true);
+            target.set_date_recorded(Timestamp { year: source.year, month: None, day: None, hour: None, minute: None, second: None });
+        } else {
+            report.mark("TYER", target.get("TYER").is_some());
+            target.set_year(source.year);
+        }
+    }
+
+    // Several genres are written as separate values, so "(17)" references are replaced with names
+    let genres: Vec<&str> = source.genre.split(" / ").collect();
+    if source.genre != "empty" && target.genre() != Some(genres.join("\0").as_str()) {
+        report.mark("TCON", target.genre().is_some());
+        target.set_text_values("TCON", genres);
+    }
+
+    // Total of discs is kept if it isn't known
+    if source.disc > 0 && (target.disc() != Some(source.disc) || (source.disc_total > 0 && target.total_discs() != Some(source.disc_total))) {
+        report.mark("TPOS", target.get("TPOS").is_some());
+        if source.disc_total > 0 { target.set_text("TPOS", format!("{}/{}", source.disc, source.disc_total));
+        } else {                   target.set_text("TPOS", format!("{}", source.disc)); }
+    }
+
+    if source.album_artist != "empty" && target.album_artist() != Some(source.album_artist.as_str()) {
+        report.mark("TPE2", target.album_artist().is_some());
+        target.set_album_artist(&source.album_artist);
+    }
+
+    let compilation = target.get("TCMP").and_then(|frame| frame.content().text()).map(String::from);
+    if source.compilation && compilation.as_deref() != Some("1") {
+        report.mark("TCMP", compilation.is_some());
+        target.set_text("TCMP", "1");
+    }
+
+    for (description, value) in &source.extra {
+        let old = target.extended_texts().find(|text| &text.description == description).map(|text| text.value.clone());
+
+        if old.as_ref() != Some(value) {
+            report.mark(&format!("TXXX:{}", description), old.is_some());
+            target.add_frame(ExtendedText { description: description.clone(), value: value.clone() });
+        }
+    }
+
+    for id in existing {
+        if !report.changed.contains(&id) && !report.kept.contains(&id) { report.kept.push(id); }
+    }
+
+    report
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Write tag-data to audio file
+//
+// Inputs
+// path   - path to audio file
+// format - container format as in detect_format
+// tag    - previously parsed tag data
+// orig   - tag data read from the file
+// v      - verbose status
+// dry    - only print out the changes, without writing anything
+//
+// Return: Status of the update
+//////////////////////////////////////////////////////////////////////////////////////
+fn write_tags(path: &str, format: &str, tag: &ID3TagInfo, orig: &ID3TagInfo, options: &WriteOptions, v: &str, dry: bool) -> FileStatus {
+    let error = is_empty(tag) && tag.genre == "empty" && tag.album_artist == "empty" && tag.disc == 0 && !tag.compilation && tag.extra.is_empty();
+
+    if !error {
+        let count = compare_tags(tag, orig);
+        // Files with only ID3v1 tag or RIFF INFO list are upgraded, keeping the data that isn't overwritten
+        let (mut new_tag, source) = match read_tag_checked(path, format) {
+            Ok(found) => found.unwrap_or((Tag::new(), String::from("empty"))),
+            Err(err) => {
+                if v != "entry" { println!("Failed to read the existing tag of '{}': {}", path, err); }
+                return FileStatus::Failed;
+            }
+        };
+        let upgrade = source == "ID3v1" || source == "RIFF INFO";
+        let version: Version = options.version.unwrap_or(new_tag.version());
+        // Tags of other versions are left as they are, unless the version was given or the tag is written anyway
+        let wanted: Version = if options.convert { version } else { new_tag.version() };
+        let convert = is_id3_format(format) && needs_conversion(&new_tag, path, wanted, options.encoding);
+        let report: FrameReport = update_tag(&mut new_tag, tag);
+        let old_track: Option<String> = new_tag.get("TRCK").and_then(|frame| frame.content().text()).map(String::from);
+        let padded = options.pad_tracks && pad_track(&mut new_tag);
+        let changed = count < 5 || !compare_extra(tag, orig) || convert || upgrade || padded;
+        let text = |new: &str, old: &str| -> String { String::from(if new == "empty" { old } else { new }) };
+        let new_tag: Tag = convert_tag(&new_tag, version, options.encoding);
+
+        // ID3v1 is rebuilt from the final ID3v2 data
+        let old_v1 = id3v1::read_raw(path);
+        let v1_tag = id3v1::build(&new_tag, old_v1.map(|data| data[127]).unwrap_or(255));
+        let v1_changed = options.id3v1 && format == "mp3" && old_v1 != Some(v1_tag);
+
+        if dry {
+            if changed || v1_changed {
+                if v != "silent" { println!("Would update ID3 tags to '{}'", path); }
+                if v != "silent" && v != "entry" { print_diff(tag, orig, v); }
+                if v != "silent" && v != "entry" && (convert || upgrade) { println!("    version: '{}' -> '{}'", source, version); }
+                if v != "silent" && v != "entry" && padded { println!("    TRCK: '{}' -> '{}'", old_track.unwrap_or_default(), new_tag.get("TRCK").and_then(|frame| frame.content().text()).unwrap_or("")); }
+                if v != "silent" && v != "entry" && v1_changed { println!("    ID3v1: {}", if old_v1.is_some() { "updated" } else { "added" }); }
+                return FileStatus::Updated;
+            }
+
+            if v == "verbose" || v == "loud" { println!("No need to update, as the information already matches! '{}'", path); }
+            if v == "loud" { print_diff(tag, orig, v); }
+            return FileStatus::Skipped;
+        }
+
+        if changed || v1_changed {
+            let do_steps = || -> Result<(), Box<dyn std::error::Error>> {
+                if changed { write_tag_file(path, format, &new_tag, version)?; }
+                if v1_changed { id3v1::write(path, &v1_tag)?; }
+                Ok(())
+            };
+
+            if let Err(_err) = do_steps() {
+                if v != "entry" { println!("Failed to update ID3 to '{}'", path); }
+                FileStatus::Failed
+            } else {
+                if v != "silent" && v != "entry" { println!("Updated ID3 tags to '{}'\n    -> as artist:'{}' year:'{}' album:'{}' track:'{}' title:'{}'", path,
+                                                            text(&tag.artist, &orig.artist), if tag.year > 0 { tag.year } else { orig.year }, text(&tag.album, &orig.album), if tag.track > 0 { tag.track } else { orig.track }, text(&tag.title, &orig.title)); }
+                if v == "verbose" || v == "loud" { report.print(); }
+                if (v == "verbose" || v == "loud") && upgrade { println!("    -> upgraded from {} to {}", source, version); }
+                if (v == "verbose" || v == "loud") && v1_changed { println!("    -> ID3v1 tag {}", if old_v1.is_some() { "updated" } else { "added" }); }
+                FileStatus::Updated
+            }
+        } else {
+            if v == "verbose" || v == "loud" { println!("No need to update, as the information already matches! '{}'", path); }
+            FileStatus::Skipped
+        }
+    } else {
+        if v != "entry" { println!("Some input values are incorrect: artist:'{}' title:'{}' album:'{}' track:'{}' year:'{}'. ABORTING!", tag.artist, tag.title, tag.album, tag.track, tag.year); }
+        FileStatus::Failed
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Write tag to audio file in the format of the file
+//
+// Inputs
+// path    - path to audio file
+// format  - container format as in detect_format
+// tag     - ID3 tag handler, converted to the tag format of the file
+// version - ID3 version, used only with files holding ID3 tags
+//
+// Return: Error if the file can't be written
+//////////////////////////////////////////////////////////////////////////////////////
+fn write_tag_file(path: &str, format: &str, tag: &Tag, version: Version) -> Result<(), Box<dyn std::error::Error>> {
+    if format == "flac" { flac::write(path, tag)?;
+    } else if format == "ogg" { ogg::write(path, tag)?;
+    } else if format == "mp4" { mp4::write(path, tag)?;
+    } else { tag.write_to_path(path, version)?; } // Writes ID3 chunk to WAV and AIFF files
+
+    Ok(())
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Replace file with new content, which is first written aside, so a failure can't leave the file half written
+//
+// Inputs
+// path  - path to the replaced file
+// write - writes the new content to the temporary file
+//
+// Return: Error if the file can't be written, the temporary file is removed then
+//////////////////////////////////////////////////////////////////////////////////////
+fn replace_file<F: FnOnce(&mut fs::File) -> std::io::Result<()>>(path: &str, write: F) -> std::io::Result<()> {
+    let metadata = fs::metadata(path)?;
+
+    // Temporary file is created next to the file with a new name, so existing files are never overwritten
+    let (temp, mut file) = loop {
+        let temp = format!("{}.{}-{}.tmp", path, std::process::id(), TEMP_COUNTER.fetch_add(1, Ordering::Relaxed));
+
+        match fs::OpenOptions::new().write(true).create_new(true).open(&temp) {
+            Ok(file) => break (temp, file),
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    };
+
+    let result = (|| {
+        file.set_permissions(metadata.permissions())?;
+
+        // Ownership can be changed only by root, other users keep owning the files they write
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let _ = std::os::unix::fs::fchown(&file, Some(metadata.uid()), Some(metadata.gid()));
+        }
+
+        write(&mut file)?;
+        file.sync_all()?;
+        fs::rename(&temp, path)
+    })();
+
+    if result.is_err() { let _ = fs::remove_file(&temp); }
+    result
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Check if the format holds ID3 tags
+//////////////////////////////////////////////////////////////////////////////////////
+fn is_id3_format(format: &str) -> bool {
+    format == "mp3" || format == "wav" || format == "aiff"
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Copy full tag from one audio file to another, mapping the fields between formats
+//
+// Inputs
+// source  - path to audio file the tag is read from
+// target  - path to audio file the tag is written to
+// format  - container format of the target as in detect_format
+// orig    - tag data read from the target file
+// options - version and encoding of written ID3 tags
+// v       - verbose status
+// dry     - only print out the changes, without writing anything
+//
+// Return: Status of the copy
+//////////////////////////////////////////////////////////////////////////////////////
+fn copy_tags(source: &str, target: &str, format: &str, orig: &ID3TagInfo, options: &WriteOptions, v: &str, dry: bool) -> FileStatus {
+    let (source_tag, _source) = match read_tag(source, detect_format(source)) {
+        Some(found) => found,
+        None => {
+            if v != "entry" { println!("No tags to copy in '{}'", source); }
+            return FileStatus::Failed;
+        }
+    };
+
+    let (old_tag, _target) = match read_tag_checked(target, format) {
+        Ok(found) => found.unwrap_or((Tag::new(), String::from("empty"))),
+        Err(err) => {
+            if v != "entry" { println!("Failed to read the existing tag of '{}': {}", target, err); }
+            return FileStatus::Failed;
+        }
+    };
+
+    // Copied frames replace the text, comment, lyrics and picture frames of the target, other frames are kept
+    let mut new_tag = Tag::new();
+    for frame in old_tag.frames().filter(|frame| !is_copied_frame(frame.id())) {
+        new_tag.add_frame(frame.clone());
+    }
+
+    for frame in source_tag.frames().filter(|frame| is_copied_frame(frame.id())) {
+        new_tag.add_frame(frame.clone());
+    }
+
+    let version: Version = options.version.unwrap_or(old_tag.version());
+    let new_tag: Tag = if is_id3_format(format) { convert_tag(&new_tag, version, options.encoding)
+    } else if format == "mp4" { mp4::normalize(&new_tag)
+    } else { vorbis::normalize(&new_tag) };
+    let info = ID3TagInfo::from_tag(&new_tag, "empty");
+
+    if same_frames(&new_tag, &old_tag) {
+        if v == "verbose" || v == "loud" { println!("No need to copy, as the information already matches! '{}'", target); }
+        if v == "loud" { print_diff(&info, orig, v); }
+        return FileStatus::Skipped;
+    }
+
+    if dry {
+        if v != "silent" { println!("Would copy tags from '{}' to '{}'", source, target); }
+        if v != "silent" && v != "entry" { print_diff(&info, orig, v); }
+        return FileStatus::Updated;
+    }
+
+    if let Err(err) = write_tag_file(target, format, &new_tag, version) {
+        if v != "entry" { println!("Failed to copy tags to '{}': {}", target, err); }
+        FileStatus::Failed
+    } else {
+        if v != "silent" && v != "entry" { println!("Copied tags from '{}' to '{}'", source, target); }
+        if v == "verbose" || v == "loud" { print_diff(&info, orig, v); }
+        FileStatus::Updated
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Check if the frame is copied by copy-tags: text, URL, comment, lyrics or picture frame
+//////////////////////////////////////////////////////////////////////////////////////
+fn is_copied_frame(id: &str) -> bool {
+    id.starts_with('T') || id.starts_with('W') || id == "COMM" || id == "USLT" || id == "APIC"
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Check if two tags have the same frames, in any order
+//////////////////////////////////////////////////////////////////////////////////////
+fn same_frames(first: &Tag, second: &Tag) -> bool {
+    first.frames().count() == second.frames().count() && first.frames().all(|frame| second.frames().any(|other| other == frame))
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Pair source and target files for copy-tags
+//
+// Inputs
+// source - source file or directory
+// target - target file or directory
+// v      - verbose status
+//
+// Return: List of source and target file pairs. Directory files are matched by relative path
+//         without extension, and then by track number within the same relative directory
+//////////////////////////////////////////////////////////////////////////////////////
+fn pair_files(source: &str, target: &str, v: &str) -> Vec<(String, String)> {
+    let mut pairs: Vec<(String, String)> = Vec::new();
+
+    if !Path::new(source).is_dir() || !Path::new(target).is_dir() {
+        pairs.push((String::from(source), String::from(target)));
+        return pairs;
+    }
+
+    let mut sources: Vec<String> = Vec::new();
+    let mut targets: Vec<String> = Vec::new();
+    collect_files(source, &mut sources, &mut Vec::new());
+    collect_files(target, &mut targets, &mut Vec::new());
+
+    match_files(source, sources, target, targets, v)
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Match files of the source and target directories
+//
+// Inputs
+// source  - source directory
+// sources - files found from the source directory
+// target  - target directory
+// targets - files found from the target directory
+// v       - verbose status
+//
+// Return: List of source and target file pairs, as in pair_files
+//////////////////////////////////////////////////////////////////////////////////////
+fn match_files(source: &str, sources: Vec<String>, target: &str, mut targets: Vec<String>, v: &str) -> Vec<(String, String)> {
+    let mut pairs: Vec<(String, String)> = Vec::new();
+
+    let relative = |path: &str, root: &str| -> PathBuf {
+        Path::new(path).strip_prefix(root).map(|rest| rest.with_extension("")).unwrap_or_default()
+    };
+
+    let track_of = |path: &str| -> u32 {
+        let info = ID3TagInfo::read(path, detect_format(path));
+        if info.track > 0 { info.track } else { ID3TagInfo::parse(path, "empty").track }
+    };
+
+    let mut unmatched: Vec<String> = Vec::new();
+
+    for path in sources {
+        match targets.iter().position(|other| relative(other, target) == relative(&path, source)) {
+            Some(pos) => pairs.push((path, targets.remove(pos))),
+            None => unmatched.push(path),
+        }
+    }
+
+    for path in unmatched {
+        let dir = relative(&path, source).parent().map(|dir| dir.to_path_buf()).unwrap_or_default();
+        let track = track_of(&path);
+
+        let found = targets.iter().position(|other| {
+            relative(other, target).parent().map(|other_dir| other_dir.to_path_buf()).unwrap_or_default() == dir && track > 0 && track_of(other) == track
+        });
+
+        match found {
+            Some(pos) => pairs.push((path, targets.remove(pos))),
+            None => if v != "silent" && v != "entry" { println!("No matching target file for '{}'", path); }
+        }
+    }
+
+    pairs
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Find folder image of audio file by the name priority
+//
+// Inputs
+// path  - path to audio file
+// names - image names in priority order, "*" as extension matches jpg, jpeg and png, case is ignored
+//
+// Return: Path to the first found image, or None if the folder has none
+//////////////////////////////////////////////////////////////////////////////////////
+fn find_folder_image(path: &str, names: &[String]) -> Option<PathBuf> {
+    let folder = match Path::new(path).parent() {
+        Some(folder) if !folder.as_os_str().is_empty() => folder,
+        _ => Path::new("."),
+    };
+
+    let mut images: Vec<PathBuf> = fs::read_dir(folder).ok()?.flatten().map(|entry| entry.path()).filter(|image| {
+        image.is_file() && image.extension().and_then(|ext| ext.to_str()).map(|ext| ["jpg", "jpeg", "png"].contains(&ext.to_lowercase().as_str())) == Some(true)
+    }).collect();
+    images.sort();
+
+    for name in names {
+        let name = name.to_lowercase();
+
+        let found = images.iter().find(|image| {
+            let file_name = image.file_name().map(|file_name| file_name.to_string_lossy().to_lowercase()).unwrap_or_default();
+            match name.strip_suffix(".*") {
+                Some(stem) => image.file_stem().map(|other| other.to_string_lossy().to_lowercase()) == Some(String::from(stem)),
+                None => file_name == name,
+            }
+        });
+
+        if let Some(image) = found { return Some(image.clone()); }
+    }
+
+    None
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Parse size limit with an optional K or M suffix, like "500K"
+//
+// Return: Size in bytes, or None if the value isn't a number or is too big
+//////////////////////////////////////////////////////////////////////////////////////
+fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim().to_uppercase();
+
+    if let Some(number) = value.strip_suffix('K') { number.parse::<u64>().ok()?.checked_mul(1024)
+    } else if let Some(number) = value.strip_suffix('M') { number.parse::<u64>().ok()?.checked_mul(1024 * 1024)
+    } else { value.parse().ok() }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Embed folder image to audio file as front cover
+//
+// Inputs
+// path    - path to audio file
+// format  - container format as in detect_format
+// art     - image names, limits and replace setting
+// options - version and encoding of written ID3 tags
+// v       - verbose status
+// dry     - only print out what would be embedded, without writing anything
+//
+// Return: Status of the embedding, images over the limits are not resized but fail
+//////////////////////////////////////////////////////////////////////////////////////
+fn embed_art(path: &str, format: &str, art: &ArtOptions, options: &WriteOptions, v: &str, dry: bool) -> FileStatus {
+    let image = match find_folder_image(path, &art.names) {
+        Some(image) => image,
+        None => {
+            if v != "entry" { println!("No folder image found for '{}'", path); }
+            return FileStatus::Failed;
+        }
+    };
+
+    let data = match fs::read(&image) {
+        Ok(data) => data,
+        Err(err) => {
+            if v != "entry" { println!("Failed to read '{}': {}", image.display(), err); }
+            return FileStatus::Failed;
+        }
+    };
+
+    let mime_type = match image::mime_type(&data) {
+        Some(mime_type) => mime_type,
+        None => {
+            if v != "entry" { println!("Image '{}' is not a JPEG or PNG image", image.display()); }
+            return FileStatus::Failed;
+        }
+    };
+
+    if art.max_size > 0 && data.len() as u64 > art.max_size {
+        if v != "entry" { println!("Image '{}' is {} bytes, over the limit of {} bytes", image.display(), data.len(), art.max_size); }
+        return FileStatus::Failed;
+    }
+
+    if let Some((max_width, max_height)) = art.max_dimensions {
+        match image::dimensions(&data) {
+            Some((width, height)) if width <= max_width && height <= max_height => {}
+            Some((width, height)) => {
+                if v != "entry" { println!("Image '{}' is {}x{}, over the limit of {}x{}", image.display(), width, height, max_width, max_height); }
+                return FileStatus::Failed;
+            }
+            None => {
+                if v != "entry" { println!("Can't read dimensions of '{}'", image.display()); }
+                return FileStatus::Failed;
+            }
+        }
+    }
+
+    let (mut tag, _source) = match read_tag_checked(path, format) {
+        Ok(found) => found.unwrap_or((Tag::new(), String::from("empty"))),
+        Err(err) => {
+            if v != "entry" { println!("Failed to read the existing tag of '{}': {}", path, err); }
+            return FileStatus::Failed;
+        }
+    };
+    let covers: Vec<&Picture> = tag.pictures().filter(|picture| picture.picture_type == PictureType::CoverFront).collect();
+
+    if covers.iter().any(|picture| picture.data == data) {
+        if v == "verbose" || v == "loud" { println!("No need to embed, '{}' already has the image!", path); }
+        return FileStatus::Skipped;
+    }
+
+    if !covers.is_empty() && !art.replace {
+        if v == "verbose" || v == "loud" { println!("'{}' already has a front cover, use --replace-art to replace it", path); }
+        return FileStatus::Skipped;
+    }
+
+    if dry {
+        if v != "silent" { println!("Would embed '{}' to '{}'", image.display(), path); }
+        return FileStatus::Updated;
+    }
+
+    tag.remove_picture_by_type(PictureType::CoverFront);
+    tag.add_frame(Picture { mime_type: String::from(mime_type), picture_type: PictureType::CoverFront, description: String::new(), data });
+
+    let version: Version = options.version.unwrap_or(tag.version());
+    let tag: Tag = if is_id3_format(format) { convert_tag(&tag, version, options.encoding) } else { tag };
+
+    if let Err(err) = write_tag_file(path, format, &tag, version) {
+        if v != "entry" { println!("Failed to embed image to '{}': {}", path, err); }
+        FileStatus::Failed
+    } else {
+        if v != "silent" && v != "entry" { println!("Embedded '{}' to '{}'", image.display(), path); }
+        FileStatus::Updated
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Extract front cover, or the first picture, of audio file to its folder
+//
+// Inputs
+// path      - path to audio file
+// format    - container format as in detect_format
+// art       - image names, the first one names the written file, and replace setting
+// extracted - images already written, so each folder image is written only once
+// v         - verbose status
+// dry       - only print out what would be extracted, without writing anything
+//
+// Return: Status of the extraction
+//////////////////////////////////////////////////////////////////////////////////////
+fn extract_art(path: &str, format: &str, art: &ArtOptions, extracted: &mut Vec<PathBuf>, v: &str, dry: bool) -> FileStatus {
+    let tag: Tag = read_tag(path, format).map(|(tag, _source)| tag).unwrap_or_default();
+    let picture = match tag.pictures().find(|picture| picture.picture_type == PictureType::CoverFront).or(tag.pictures().next()) {
+        Some(picture) => picture,
+        None => {
+            if v != "entry" { println!("No picture in '{}'", path); }
+            return FileStatus::Failed;
+        }
+    };
+
+    // Extension follows the image data, as the MIME type of the frame may be wrong
+    let extension = image::extension(image::mime_type(&picture.data).unwrap_or(&picture.mime_type));
+    let name = art.names.first().map(|name| name.as_str()).unwrap_or("cover.*");
+    let name = match name.strip_suffix(".*") {
+        Some(stem) => format!("{}.{}", stem, extension),
+        None => String::from(name),
+    };
+    let target = Path::new(path).parent().unwrap_or(Path::new("")).join(name);
+
+    if extracted.contains(&target) || fs::read(&target).ok() == Some(picture.data.clone()) {
+        if v == "verbose" || v == "loud" { println!("No need to extract, '{}' is already written!", target.display()); }
+        return FileStatus::Skipped;
+    }
+
+    if target.exists() && !art.replace {
+        if v == "verbose" || v == "loud" { println!("'{}' already exists, use --replace-art to replace it", target.display()); }
+        return FileStatus::Skipped;
+    }
+
+    extracted.push(target.clone());
+
+    if dry {
+        if v != "silent" { println!("Would extract picture of '{}' to '{}'", path, target.display()); }
+        return FileStatus::Updated;
+    }
+
+    if let Err(err) = fs::write(&target, &picture.data) {
+        if v != "entry" { println!("Failed to write '{}': {}", target.display(), err); }
+        FileStatus::Failed
+    } else {
+        if v != "silent" && v != "entry" { println!("Extracted picture of '{}' to '{}'", path, target.display()); }
+        FileStatus::Updated
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Rebuild ID3v1 tag from the existing ID3v2 tag
+//
+// Inputs
+// path   - path to audio file
+// format - container format as in detect_format
+// v      - verbose status
+// dry    - only print out what would be done
+//
+// Return: Status of the sync
+//////////////////////////////////////////////////////////////////////////////////////
+fn sync_v1(path: &str, format: &str, v: &str, dry: bool) -> FileStatus {
+    // ID3v1 appended to other containers would end up outside of their data
+    if format != "mp3" {
+        if v == "verbose" || v == "loud" { println!("Only MP3 files have an ID3v1 tag, skipping '{}'", path); }
+        return FileStatus::Skipped;
+    }
+
+    let tag: Tag = match Tag::read_from_path(path) {
+        Ok(tag) => tag,
+        Err(_err) => {
+            if v != "entry" { println!("No ID3v2 tag to sync from in '{}'", path); }
+            return FileStatus::Failed;
+        }
+    };
+
+    let old_v1 = id3v1::read_raw(path);
+    let v1_tag = id3v1::build(&tag, old_v1.map(|data| data[127]).unwrap_or(255));
+
+    if old_v1 == Some(v1_tag) {
+        if v == "verbose" || v == "loud" { println!("No need to sync, ID3v1 already matches! '{}'", path); }
+        return FileStatus::Skipped;
+    }
+
+    if dry {
+        if v != "silent" { println!("Would {} ID3v1 tag to '{}'", if old_v1.is_some() { "update" } else { "add" }, path); }
+        return FileStatus::Updated;
+    }
+
+    if let Err(err) = id3v1::write(path, &v1_tag) {
+        if v != "entry" { println!("Failed to write ID3v1 to '{}': {}", path, err); }
+        FileStatus::Failed
+    } else {
+        if v != "silent" && v != "entry" { println!("Synced ID3v1 tag to '{}'", path); }
+        FileStatus::Updated
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Parse text encoding name given from the commandline
+//
+// Inputs
+// name - latin1, utf16, utf16be or utf8
+//
+// Return: Encoding, or None if the name is unknown
+//////////////////////////////////////////////////////////////////////////////////////
+fn parse_encoding(name: &str) -> Option<Encoding> {
+    let name = name.to_lowercase().replace(['-', '_'], "");
+
+    if        name == "latin1" || name == "iso88591" { Some(Encoding::Latin1)
+    } else if name == "utf16"                        { Some(Encoding::UTF16)
+    } else if name == "utf16be"                      { Some(Encoding::UTF16BE)
+    } else if name == "utf8"                         { Some(Encoding::UTF8)
+    } else { None }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Choose text encoding for a frame that is valid for the written version
+//
+// Inputs
+// frame    - frame to be written
+// version  - written ID3 version
+// encoding - wanted encoding, or None to keep the encoding of the frame, if it's known
+//
+// Return: Encoding for the frame, or None to use the default of the version
+//////////////////////////////////////////////////////////////////////////////////////
+fn choose_encoding(frame: &Frame, version: Version, encoding: Option<Encoding>) -> Option<Encoding> {
+    let wanted = encoding.or(frame.encoding());
+
+    // UTF-8 and UTF-16BE exist only in ID3v2.4, and Latin-1 can't hold all characters
+    let unicode_only = (wanted == Some(Encoding::UTF8) || wanted == Some(Encoding::UTF16BE)) && version != Version::Id3v24;
+    let not_latin1 = wanted == Some(Encoding::Latin1) && frame.content().to_string().chars().any(|c| c as u32 > 0xff);
+
+    if unicode_only || not_latin1 { Some(Encoding::UTF16) } else { wanted }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Read ID3v2 tag layout straight from the start of the file, as it is not kept when decoding
+//
+// Inputs
+// path - path to audio file
+//
+// Return: Tag layout, or None if the file doesn't start with an ID3v2 tag
+//////////////////////////////////////////////////////////////////////////////////////
+fn read_raw_tag(path: &str) -> Option<RawTag> {
+    let mut header = [0u8; 10];
+    let mut file = fs::File::open(path).ok()?;
+
+    if file.read_exact(&mut header).is_err() || &header[0..3] != b"ID3" { return None; }
+
+    let size = syncsafe(&header[6..10]);
+    let footer: usize = if header[3] == 4 && header[5] & 0x10 != 0 { 10 } else { 0 };
+    let mut raw = RawTag { version: header[3], size: size + 10 + footer, padding: None, frames: Vec::new() };
+
+    // Unsynchronised tags would need decoding first, and ID3v2.2 frames have another layout
+    if header[5] & 0x80 != 0 || header[3] < 3 { return Some(raw); }
+
+    let mut data = vec![0u8; size];
+    if file.read_exact(&mut data).is_err() { return Some(raw); }
+
+    let mut pos: usize = 0;
+
+    if header[5] & 0x40 != 0 && data.len() >= 4 {
+        let ext = if header[3] == 4 { syncsafe(&data[0..4]) } else { 4 + u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize };
+        pos = ext;
+    }
+
+    while pos + 11 <= data.len() && data[pos] != 0 {
+        let id = String::from_utf8_lossy(&data[pos..pos + 4]).into_owned();
+        let len = if header[3] == 4 { syncsafe(&data[pos + 4..pos + 8]) } else { u32::from_be_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]]) as usize };
+
+        raw.frames.push((id, len, data[pos + 10]));
+        pos += 10 + len;
+    }
+
+    raw.padding = Some(data.len().saturating_sub(pos));
+    Some(raw)
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Check if the frame starts with a text encoding byte
+//////////////////////////////////////////////////////////////////////////////////////
+fn has_encoding(id: &str) -> bool {
+    id.starts_with('T') || id == "COMM" || id == "USLT" || id == "APIC"
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Read text encodings of the frames straight from the ID3v2 stream, as they are not kept when decoding
+//
+// Inputs
+// path - path to audio file
+//
+// Return: list of frame IDs and their encoding bytes, for the frames that have one
+//////////////////////////////////////////////////////////////////////////////////////
+fn read_text_encodings(path: &str) -> Vec<(String, u8)> {
+    match read_raw_tag(path) {
+        Some(raw) => raw.frames.into_iter().filter(|(id, _, _)| has_encoding(id)).map(|(id, _, encoding)| (id, encoding)).collect(),
+        None => Vec::new(),
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Decode 28-bit syncsafe integer
+//////////////////////////////////////////////////////////////////////////////////////
+fn syncsafe(bytes: &[u8]) -> usize {
+    bytes.iter().take(4).fold(0, |value, byte| (value << 7) | (*byte as usize & 0x7f))
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Check if an existing tag has to be rewritten to match the wanted version and encoding
+//
+// Inputs
+// tag      - tag read from the file
+// path     - path to audio file, for checking the stored encodings
+// version  - wanted ID3 version
+// encoding - wanted encoding, or None to use the default of the version
+//
+// Return: true if the tag differs from the wanted output
+//////////////////////////////////////////////////////////////////////////////////////
+fn needs_conversion(tag: &Tag, path: &str, version: Version, encoding: Option<Encoding>) -> bool {
+    if tag.frames().count() == 0 { return false; }
+    if tag.version() != version { return true; }
+
+    // Date frames of the other version
+    let dates: [&str; 3] = if version == Version::Id3v24 { ["TYER", "TDAT", "TORY"] } else { ["TDRC", "TDOR", "TDRL"] };
+    if dates.iter().any(|id| tag.get(id).is_some()) { return true; }
+
+    if encoding.is_none() { return false; }
+
+    read_text_encodings(path).iter().any(|(id, stored)| {
+        match tag.get(id) {
+            Some(frame) => choose_encoding(frame, version, encoding).map(|wanted| wanted as u8) != Some(*stored),
+            None => false,
+        }
+    })
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Pad track number with zeros to the width of the total, at least to two digits, like "03/12"
+//
+// Inputs
+// tag - tag to be written
+//
+// Return: true if the track text was changed
+//////////////////////////////////////////////////////////////////////////////////////
+fn pad_track(tag: &mut Tag) -> bool {
+    let track = match tag.track() {
+        Some(track) => track,
+        None => return false,
+    };
+
+    let width = tag.total_tracks().map(|total| total.to_string().len()).unwrap_or(0).max(2);
+    let text = match tag.total_tracks() {
+        Some(total) => format!("{:0width$}/{}", track, total, width = width),
+        None => format!("{:0width$}", track, width = width),
+    };
+
+    if tag.get("TRCK").and_then(|frame| frame.content().text()) == Some(text.as_str()) { return false; }
+
+    tag.set_text("TRCK", text);
+    true
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Convert tag frames for the written ID3 version and text encoding
+//
+// ID3v2.3 stores the recording time as TYER (yyyy), TDAT (DDMM) and TIME (HHMM) and the
+// original release year as TORY, while ID3v2.4 uses TDRC and TDOR timestamps instead.
+//
+// Inputs
+// tag      - tag to be written
+// version  - written ID3 version
+// encoding - wanted encoding, or None to use the default of the version
+//
+// Return: Converted tag
+//////////////////////////////////////////////////////////////////////////////////////
+fn convert_tag(tag: &Tag, version: Version, encoding: Option<Encoding>) -> Tag {
+    let mut target: Tag = tag.clone();
+
+    if version == Version::Id3v24 {
+        if let Some(year) = target.year() {
+            let date = target.text_for_frame_id("TDAT").map(String::from).unwrap_or_default();
+            let time = target.text_for_frame_id("TIME").map(String::from).unwrap_or_default();
+            let pair = |text: &str, pos: usize| text.get(pos..pos + 2).and_then(|value| value.parse::<u8>().ok());
+
+            if target.date_recorded().map(|date| date.year) != Some(year) {
+                let (day, month) = if date.len() == 4 { (pair(&date, 0), pair(&date, 2)) } else { (None, None) };
+                let (hour, minute) = if time.len() == 4 && day.is_some() { (pair(&time, 0), pair(&time, 2)) } else { (None, None) };
+
+                target.set_date_recorded(Timestamp { year, month, day, hour, minute, second: None });
+            }
+
+            target.remove("TYER");
+            target.remove("TDAT");
+            target.remove("TIME");
+        }
+
+        if let Some(year) = target.text_for_frame_id("TORY").and_then(|text| text.trim().parse::<i32>().ok()) {
+            if target.original_date_released().is_none() {
+                target.set_original_date_released(Timestamp { year, month: None, day: None, hour: None, minute: None, second: None });
+            }
+            target.remove("TORY");
+        }
+    } else {
+        if let Some(date) = target.date_recorded() {
+            if target.year().is_none() {
+                target.set_year(date.year);
+
+                if let (Some(month), Some(day)) = (date.month, date.day) {
+                    target.set_text("TDAT", format!("{:02}{:02}", day, month));
+                }
+                if let (Some(hour), Some(minute)) = (date.hour, date.minute) {
+                    target.set_text("TIME", format!("{:02}{:02}", hour, minute));
+                }
+            }
+
+            target.remove("TDRC");
+        }
+
+        if let Some(date) = target.original_date_released() {
+            if target.get("TORY").is_none() { target.set_text("TORY", format!("{:04}", date.year)); }
+            target.remove("TDOR");
+        }
+    }
+
+    target.frames().map(|frame| {
+        let encoding = choose_encoding(frame, version, encoding);
+        frame.clone().set_encoding(encoding)
+    }).collect()
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// BatchSummary struct handlers
+//////////////////////////////////////////////////////////////////////////////////////
+impl BatchSummary {
+    pub fn new() -> BatchSummary {
+        BatchSummary { updated: 0, skipped: 0, failed: 0 }
+    }
+
+    //////////////////////////////////////////////////////////////////////////////////////
+    // Count handled file
+    //
+    // Inputs
+    // status - outcome of the file handling
+    //////////////////////////////////////////////////////////////////////////////////////
+    pub fn add(&mut self, status: FileStatus) {
+        if        status == FileStatus::Updated { self.updated += 1;
+        } else if status == FileStatus::Skipped { self.skipped += 1;
+        } else {                                  self.failed  += 1; }
+    }
+
+    //////////////////////////////////////////////////////////////////////////////////////
+    // Print out the summary
+    //
+    // Inputs
+    // command - handled command
+    //////////////////////////////////////////////////////////////////////////////////////
+    pub fn print(&self, command: &str) {
+        println!("{}", self.line(command));
+    }
+
+    //////////////////////////////////////////////////////////////////////////////////////
+    // Get the summary line
+    //
+    // Inputs
+    // command - handled command, naming the counted outcomes
+    //////////////////////////////////////////////////////////////////////////////////////
+    pub fn line(&self, command: &str) -> String {
+        let done = if command == "remove" { "Removed" } else if command == "check" { "Mismatched" } else if command == "copy-tags" { "Copied" } else if command == "sync-v1" { "Synced" } else if command == "rename" { "Renamed" } else if command == "organize" { "Organized" } else if command == "art embed" { "Embedded" } else if command == "art extract" { "Extracted" } else { "Updated" };
+        let skip = if command == "remove" { "already empty" } else if command == "check" { "matching" } else if command == "sync-v1" { "already in sync" } else if command == "rename" { "already named" } else if command == "organize" { "already in place" } else if command == "art embed" { "already embedded" } else if command == "art extract" { "already extracted" } else { "already matching" };
+
+        format!("{}: {}, skipped ({}): {}, failed: {}", done, self.updated, skip, self.skipped, self.failed)
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Check if file is handled as an audio file
+//
+// Inputs
+// path - path to file
+//
+// Return: true if the file is handled when walking through directories, files with an audio
+//         extension always, images, texts and playlists never, and other files if their content
+//         is recognized, so files with a wrong or missing extension are found too
+//////////////////////////////////////////////////////////////////////////////////////
+fn is_audio_file(path: &Path) -> bool {
+    let ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or("").to_lowercase();
+
+    if AUDIO_EXTENSIONS.contains(&ext.as_str()) { true
+    } else if OTHER_EXTENSIONS.contains(&ext.as_str()) { false
+    } else { path.to_str().and_then(sniff_format).is_some() }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Detect format of audio file, from its content if possible, otherwise from its extension
+//
+// Inputs
+// path - path to audio file
+//
+// Return: "flac" for FLAC files, "ogg" for Ogg Vorbis and Opus files, "mp4" for AAC and ALAC files,
+//         "wav" and "aiff" for ID3 chunks in RIFF and AIFF files, "mp3" for everything else
+//////////////////////////////////////////////////////////////////////////////////////
+fn detect_format(path: &str) -> &'static str {
+    sniff_format(path).or_else(|| extension_format(path)).unwrap_or("mp3")
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Detect format of audio file from the magic bytes at its start
+//
+// Inputs
+// path - path to audio file
+//
+// Return: Format as in detect_format, or None if the content is not recognized
+//////////////////////////////////////////////////////////////////////////////////////
+fn sniff_format(path: &str) -> Option<&'static str> {
+    let mut file = fs::File::open(path).ok()?;
+    let mut data: Vec<u8> = Vec::new();
+    (&mut file).take(SNIFF_SIZE).read_to_end(&mut data).ok()?;
+
+    // ID3v2 tag can be in front of MPEG and FLAC streams
+    if data.len() >= 10 && &data[0..3] == b"ID3" {
+        let footer: u64 = if data[5] & 0x10 != 0 { 10 } else { 0 };
+        file.seek(SeekFrom::Start(syncsafe(&data[6..10]) as u64 + 10 + footer)).ok()?;
+
+        let mut magic = [0u8; 4];
+        if file.read_exact(&mut magic).is_ok() && &magic == b"fLaC" { return Some("flac"); }
+        return Some("mp3");
+    }
+
+    sniff_data(&data)
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Detect format of audio data without an ID3v2 tag in front
+//
+// Inputs
+// data - start of the file
+//
+// Return: Format as in detect_format, or None if the content is not recognized
+//////////////////////////////////////////////////////////////////////////////////////
+fn sniff_data(data: &[u8]) -> Option<&'static str> {
+    let magic = data.get(0..4)?;
+
+    if magic == b"fLaC" { Some("flac")
+    } else if magic == b"OggS" { Some("ogg")
+    } else if data.get(4..8) == Some(b"ftyp") { Some("mp4")
+    } else if magic == b"RIFF" && data.get(8..12) == Some(b"WAVE") { Some("wav")
+    } else if magic == b"FORM" && (data.get(8..12) == Some(b"AIFF") || data.get(8..12) == Some(b"AIFC")) { Some("aiff")
+    } else if is_mpeg_stream(data) { Some("mp3")
+    } else { None }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Check that data starts with two consecutive MPEG audio frames
+//
+// Return: true if the first frame header is valid and the next frame starts right after it,
+//         a lone frame sync matches too much, like the UTF-16 byte order mark FF FE
+//////////////////////////////////////////////////////////////////////////////////////
+fn is_mpeg_stream(data: &[u8]) -> bool {
+    match mpeg_frame_length(data) {
+        Some(length) => data.len() >= length + 4 && mpeg_frame_length(&data[length..]).is_some(),
+        None => false,
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Get length of MPEG audio frame from its header
+//
+// Inputs
+// header - data starting with the frame header
+//
+// Return: Frame length in bytes, or None if the header is not valid or uses a free bitrate
+//////////////////////////////////////////////////////////////////////////////////////
+fn mpeg_frame_length(header: &[u8]) -> Option<usize> {
+    const BITRATES: [[u32; 15]; 5] = [
+        [0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448],   // MPEG-1 layer I
+        [0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384],      // MPEG-1 layer II
+        [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320],       // MPEG-1 layer III
+        [0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256],      // MPEG-2 and 2.5 layer I
+        [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],           // MPEG-2 and 2.5 layers II and III
+    ];
+    const SAMPLE_RATES: [u32; 3] = [44100, 48000, 32000];
+
+    let header = header.get(0..4)?;
+    if header[0] != 0xff || header[1] & 0xe0 != 0xe0 { return None; }
+
+    let version = (header[1] >> 3) & 0x03;                          // 0 MPEG-2.5, 1 reserved, 2 MPEG-2, 3 MPEG-1
+    let layer = (header[1] >> 1) & 0x03;                            // 0 reserved, 1 layer III, 2 layer II, 3 layer I
+    let bitrate_index = (header[2] >> 4) as usize;
+    let rate_index = ((header[2] >> 2) & 0x03) as usize;
+    let padding = ((header[2] >> 1) & 0x01) as u32;
+
+    if version == 1 || layer == 0 || bitrate_index == 0 || bitrate_index == 15 || rate_index == 3 { return None; }
+
+    let table = match (version == 3, layer) {
+        (true, 3) => 0, (true, 2) => 1, (true, _) => 2,
+        (false, 3) => 3, (false, _) => 4,
+    };
+    let bitrate = BITRATES[table][bitrate_index] * 1000;
+    let sample_rate = SAMPLE_RATES[rate_index] >> match version { 3 => 0, 2 => 1, _ => 2 };
+
+    let length = if layer == 3 { (12 * bitrate / sample_rate + padding) * 4
+    } else if layer == 1 && version != 3 { 72 * bitrate / sample_rate + padding
+    } else { 144 * bitrate / sample_rate + padding };
+
+    Some(length as usize)
+}
 
-    if !error {
-        let count = compare_tags(tag, orig);
+//////////////////////////////////////////////////////////////////////////////////////
+// Detect format of audio file from its extension
+//
+// Inputs
+// path - path to audio file
+//
+// Return: Format as in detect_format, or None if the extension is not handled
+//////////////////////////////////////////////////////////////////////////////////////
+fn extension_format(path: &str) -> Option<&'static str> {
+    let ext = Path::new(path).extension().and_then(|ext| ext.to_str()).unwrap_or("").to_lowercase();
+
+    if ext == "mp3" { Some("mp3")
+    } else if ext == "flac" { Some("flac")
+    } else if ext == "ogg" || ext == "oga" || ext == "opus" { Some("ogg")
+    } else if ext == "m4a" || ext == "m4b" { Some("mp4")
+    } else if ext == "wav" { Some("wav")
+    } else if ext == "aif" || ext == "aiff" || ext == "aifc" { Some("aiff")
+    } else { None }
+}
 
-        if count < 5 {
-            let new_tag: Tag = get_tag(tag);
+//////////////////////////////////////////////////////////////////////////////////////
+// Get printable name of the container format
+//////////////////////////////////////////////////////////////////////////////////////
+fn format_name(format: &str) -> &'static str {
+    if format == "flac" { "FLAC"
+    } else if format == "ogg" { "Ogg"
+    } else if format == "mp4" { "MP4"
+    } else if format == "wav" { "WAV"
+    } else if format == "aiff" { "AIFF"
+    } else { "MP3" }
+}
 
-            let do_steps = || -> Result<(), Box<dyn std::error::Error>> {
-                new_tag.write_to_path(path, Version::Id3v24)?;
-                Ok(())
-            };
+//////////////////////////////////////////////////////////////////////////////////////
+// Check that the extension of the file matches its content
+//
+// Inputs
+// path    - path to audio file
+// content - format detected from the content, as in sniff_format
+// v       - verbose status
+//
+// Return: Updated if the extension doesn't match, Skipped if it does, Failed if the content is unknown
+//////////////////////////////////////////////////////////////////////////////////////
+fn check_file(path: &str, content: Option<&'static str>, v: &str) -> FileStatus {
+    let content = match content {
+        Some(format) => format,
+        None => {
+            if v != "entry" { println!("Unknown audio content in '{}'", path); }
+            return FileStatus::Failed;
+        }
+    };
 
-            if let Err(_err) = do_steps() {
-                if v != "entry" { println!("Failed to update ID3 to '{}'", path); }
-            } else {
-                if v != "silent" && v != "entry" { println!("Updated ID3 tags to '{}'\n    -> as artist:'{}' year:'{}' album:'{}' track:'{}' title:'{}'", path, tag.artist, tag.year, tag.album, tag.track, tag.title); }
-            }
-        } else if v == "verbose" || v == "loud" { println!("No need to update, as the information already matches! '{}'", path); }
-    } else if v != "entry" {
-        println!("Some input values are incorrect: artist:'{}' title:'{}' album:'{}' track:'{}' year:'{}'. ABORTING!", tag.artist, tag.title, tag.album, tag.track, tag.year);
+    if extension_format(path) == Some(content) {
+        if v == "verbose" || v == "loud" { println!("Extension matches {} content: '{}'", format_name(content), path); }
+        FileStatus::Skipped
+    } else {
+        if v != "silent" && v != "entry" { println!("Extension doesn't match {} content: '{}'", format_name(content), path); }
+        FileStatus::Updated
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Collect audio files from path, walking through directories recursively
+//
+// Inputs
+// path    - path to file or directory
+// files   - list where the found files are added
+// visited - directories already walked through, so symlinks looping back are walked only once
+//////////////////////////////////////////////////////////////////////////////////////
+fn collect_files(path: &str, files: &mut Vec<String>, visited: &mut Vec<PathBuf>) {
+    if !Path::new(path).is_dir() {
+        files.push(String::from(path));
+        return;
+    }
+
+    match fs::canonicalize(path) {
+        Ok(real) if visited.contains(&real) => return,
+        Ok(real) => visited.push(real),
+        Err(_err) => { println!("Failed to read directory '{}'", path); return; }
+    }
+
+    let mut entries: Vec<PathBuf> = match fs::read_dir(path) {
+        Ok(dir) => dir.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect(),
+        Err(_err) => { println!("Failed to read directory '{}'", path); return; }
+    };
+
+    entries.sort();
+
+    for entry in entries {
+        if entry.is_dir() {
+            collect_files(&entry.to_string_lossy(), files, visited);
+        } else if is_audio_file(&entry) {
+            files.push(entry.to_string_lossy().into_owned());
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Rename audio file based on its tag data
+//
+// Inputs
+// path   - path to audio file
+// info   - tag data read from the file
+// tokens - file name template, the original file extension is kept
+// used   - new paths already taken during this run, for detecting collisions
+// v      - verbose status
+// dry    - only print out the new name, without renaming anything
+//
+// Return: Status of the rename
+//////////////////////////////////////////////////////////////////////////////////////
+fn rename_file(path: &str, info: &ID3TagInfo, tokens: &[PatternToken], used: &mut Vec<PathBuf>, v: &str, dry: bool) -> FileStatus {
+    let source = Path::new(path);
+    let mut name = match render_pattern(tokens, info) {
+        Ok(name) => name,
+        Err(field) => {
+            if v != "entry" { println!("No '{}' in tag data, can't rename '{}'", field, path); }
+            return FileStatus::Failed;
+        }
+    };
+
+    if let Some(ext) = source.extension().and_then(|ext| ext.to_str()) {
+        if !name.to_lowercase().ends_with(&format!(".{}", ext.to_lowercase())) { name = format!("{}.{}", name, ext); }
+    }
+
+    let target = source.with_file_name(&name);
+
+    if target == source {
+        if v == "verbose" || v == "loud" { println!("No need to rename, name already matches! '{}'", path); }
+        return FileStatus::Skipped;
+    }
+
+    if used.contains(&target) || (target.exists() && !same_file(source, &target)) {
+        if v != "entry" { println!("Can't rename '{}', '{}' already exists!", path, target.display()); }
+        return FileStatus::Failed;
+    }
+
+    used.push(target.clone());
+
+    if dry {
+        if v != "silent" { println!("Would rename '{}'\n    -> '{}'", path, name); }
+        return FileStatus::Updated;
+    }
+
+    if let Err(err) = fs::rename(source, &target) {
+        if v != "entry" { println!("Failed to rename '{}': {}", path, err); }
+        FileStatus::Failed
+    } else {
+        if v != "silent" && v != "entry" { println!("Renamed '{}'\n    -> '{}'", path, name); }
+        FileStatus::Updated
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Rename directories based on the tag data of the files in them
+//
+// Inputs
+// dirs - directories and the names rendered from each of their files
+// v    - verbose status
+// dry  - only print out the new names, without renaming anything
+//////////////////////////////////////////////////////////////////////////////////////
+fn rename_dirs(dirs: &[(PathBuf, String)], v: &str, dry: bool) {
+    let mut handled: Vec<&PathBuf> = Vec::new();
+
+    // Deepest directories are renamed first, so the paths of nested directories are still valid when they are renamed
+    let mut order: Vec<&(PathBuf, String)> = dirs.iter().collect();
+    order.sort_by_key(|(dir, _name)| std::cmp::Reverse(dir.components().count()));
+
+    for (dir, name) in order {
+        if handled.contains(&dir) { continue; }
+        handled.push(dir);
+
+        // All files in the folder must agree on the name, otherwise it's probably a compilation or a mixed folder
+        if dirs.iter().any(|(other, other_name)| other == dir && other_name != name) {
+            if v != "entry" { println!("Files in '{}' have different album info, not renaming the directory", dir.display()); }
+            continue;
+        }
+
+        let target = dir.with_file_name(name);
+
+        if &target == dir {
+            if v == "verbose" || v == "loud" { println!("No need to rename, directory name already matches! '{}'", dir.display()); }
+        } else if target.exists() && !same_file(dir, &target) {
+            if v != "entry" { println!("Can't rename '{}', '{}' already exists!", dir.display(), target.display()); }
+        } else if dry {
+            if v != "silent" { println!("Would rename directory '{}'\n    -> '{}'", dir.display(), name); }
+        } else if let Err(err) = fs::rename(dir, &target) {
+            if v != "entry" { println!("Failed to rename directory '{}': {}", dir.display(), err); }
+        } else if v != "silent" && v != "entry" {
+            println!("Renamed directory '{}'\n    -> '{}'", dir.display(), name);
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Move or copy audio file into a library tree based on its tag data
+//
+// Inputs
+// path    - path to audio file
+// info    - tag data read from the file
+// library - library root, layout relative to the root and copy mode, the original file extension is kept
+// used    - new paths already taken during this run, for detecting collisions
+// v       - verbose status
+// dry     - only print out the new location, without touching any files
+//
+// Return: Status of the move
+//////////////////////////////////////////////////////////////////////////////////////
+fn organize_file(path: &str, info: &ID3TagInfo, library: &LibraryInfo, used: &mut Vec<PathBuf>, v: &str, dry: bool) -> FileStatus {
+    let root = &library.root;
+    let copy = library.copy;
+    let source = Path::new(path);
+    let file_name = source.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let rendered = if is_empty(info) {
+        Err(String::from("No tag data"))
+    } else {
+        render_pattern(&library.layout, info).map_err(|field| format!("No '{}' in tag data", field))
+    };
+
+    // Files without enough tag data are kept with their original name
+    let mut target = match rendered {
+        Ok(name) => Path::new(root).join(name),
+        Err(reason) => {
+            if v == "verbose" || v == "loud" { println!("{}, moving to unsorted '{}'", reason, path); }
+            Path::new(root).join("_unsorted").join(&file_name)
+        }
+    };
+
+    if let Some(ext) = source.extension().and_then(|ext| ext.to_str()) {
+        if target.extension().map(|old| old.to_string_lossy().to_lowercase()) != Some(ext.to_lowercase()) {
+            let name = format!("{}.{}", target.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default(), ext);
+            target.set_file_name(name);
+        }
+    }
+
+    if target == source || same_file(source, &target) {
+        if v == "verbose" || v == "loud" { println!("No need to organize, file is already in place! '{}'", path); }
+        return FileStatus::Skipped;
+    }
+
+    if used.contains(&target) || target.exists() {
+        if v != "entry" { println!("Can't organize '{}', '{}' already exists!", path, target.display()); }
+        return FileStatus::Failed;
+    }
+
+    used.push(target.clone());
+    let action = if copy { "copy" } else { "move" };
+
+    if dry {
+        if v != "silent" { println!("Would {} '{}'\n    -> '{}'", action, path, target.display()); }
+        return FileStatus::Updated;
+    }
+
+    let do_steps = || -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(dir) = target.parent() { fs::create_dir_all(dir)?; }
+
+        if copy {
+            fs::copy(source, &target)?;
+        } else if fs::rename(source, &target).is_err() {
+            // Renaming doesn't work across filesystems, so copy and remove the original instead
+            fs::copy(source, &target)?;
+            fs::remove_file(source)?;
+        }
+        Ok(())
+    };
+
+    if let Err(err) = do_steps() {
+        if v != "entry" { println!("Failed to {} '{}': {}", action, path, err); }
+        FileStatus::Failed
+    } else {
+        if v != "silent" && v != "entry" { println!("{} '{}'\n    -> '{}'", if copy { "Copied" } else { "Moved" }, path, target.display()); }
+        FileStatus::Updated
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Check if two paths point to the same file, e.g. when only the letter case differs
+//
+// Inputs
+// first  - first path
+// second - second path
+//
+// Return: true if both paths resolve to the same file
+//////////////////////////////////////////////////////////////////////////////////////
+fn same_file(first: &Path, second: &Path) -> bool {
+    match (fs::canonicalize(first), fs::canonicalize(second)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
     }
 }
 
@@ -605,25 +2930,86 @@ fn write_tags(path: &str, tag: &ID3TagInfo, orig: &ID3TagInfo, v: &str) {
 //////////////////////////////////////////////////////////////////////////////////////
 fn print_help() {
     println!("ID3 Tag handler\n");
-    println!("params: COMMAND PATH_TO_FILE OVERWRITE_STRING\n");
+    println!("params: COMMAND PATH_TO_FILE [PATH_TO_FILE ...] OVERWRITE_STRING\n");
+    println!("PATH_TO_FILE can be a file or a directory, directories are handled recursively.");
+    println!("Several paths can be given, and a summary is printed after handling all of them.");
+    println!("MP3 files are handled with ID3 tags, FLAC, Ogg Vorbis and Opus files with Vorbis comments,");
+    println!("M4A and M4B files with iTunes item atoms, and WAV and AIFF files with ID3 chunks.");
+    println!("The format is detected from the file content, and from the extension only if the content is unknown.\n");
     println!("COMMANDS:");
-    println!("print  - print tag information from PATH_TO_FILE");
+    println!("print  - print tag information from PATH_TO_FILE, with the detected container and the tag version it was read from. ID3v1 is used if there is no ID3v2 tag");
     println!("update - update file tag infomation based on path and filename");
-    println!("remove - remove ID3 tag completely");
+    println!("remove - remove ID3 tag completely, ID3v2 by default, see --tags");
+    println!("sync-v1 - rebuild ID3v1 tag from the existing ID3v2 tag");
+    println!("check  - report files whose extension doesn't match their content");
+    println!("copy-tags SOURCE TARGET - copy full tag between files, replacing the items of the target, or between files of two directories matched by path or track number");
+    println!("rename - rename files, and optionally their directories, based on tag information");
+    println!("set    - set only the given tag items, like --artist \"ARTIST\" --track 4, and keep the rest");
+    println!("organize - move files into a library tree based on tag information, files without tags go to _unsorted");
+    println!("art embed - embed the folder image, like cover.jpg, of each file as its front cover");
+    println!("art extract - write the front cover of each file to its folder, like cover.jpg");
     println!("-v     - verbose functionality, will print more info");
     println!("-s     - silent verbose functionality, will print out only errors");
     println!("-e     - entry verbose functionality, will print only what file is being handled");
     println!("-l     - loud verbose functionality, will print all info\n");
+    println!("OPTIONS:");
+    println!("--dry-run           - print out what update, rename, organize or remove would change, without writing anything");
+    println!("--pattern TEMPLATE  - parse path with a template instead of guessing the layout");
+    println!("--regex REGEX       - parse full path with named capture groups, unknown groups are written as TXXX frames");
+    println!("--name TEMPLATE     - file name template for rename, default \"%track:02% - %title%\"");
+    println!("--rename-dir        - rename also the directories as \"%artist% - %year% - %album%\"");
+    println!("--dir-name TEMPLATE - rename also the directories with the given template");
+    println!("--library DIRECTORY - library root directory for organize");
+    println!("--layout TEMPLATE   - library layout for organize, default \"%artist%/%year% - %album%/%track:02% - %title%\"");
+    println!("--copy              - copy files with organize, instead of moving them");
+    println!("--artist, --album, --title, --genre, --albumartist, --year, --track, --disc VALUE - tag items for set, or overriding the parsed ones with update");
+    println!("--compilation       - mark files as a compilation (TCMP), with update the folder is taken as the album artist (TPE2)");
+    println!("                      and file names like \"03 - ARTIST - SONGNAME\" give the artist of each track");
+    println!("--genre-map FILE    - replace genre aliases with update and set, each line of the file is \"ALIAS = GENRE\"");
+    println!("--id3v23            - write tags as ID3v2.3, for older players, converting also tags that already match");
+    println!("--id3v24            - write tags as ID3v2.4 (default), matching tags of the other version are converted only if given");
+    println!("--keep-version      - write tags with the same version they already have");
+    println!("--encoding ENCODING - text encoding: latin1, utf16, utf16be or utf8, the last two only with ID3v2.4");
+    println!("--id3v1             - write also ID3v1.1 tag at the end of the file with update and set");
+    println!("--pad-tracks        - write track numbers padded with zeros to the width of the total, like 03/12");
+    println!("--art-names LIST    - image names for art in priority order, default \"cover.*,folder.*,front.*\"");
+    println!("--max-art-size SIZE - largest image embedded by art embed, like 500K or 2M, larger images fail");
+    println!("--max-art-dimensions WxH - largest image dimensions embedded by art embed, like 600x600");
+    println!("--replace-art       - replace existing front covers or image files with art, instead of skipping them");
+    println!("--tags TAGS         - tags handled by remove: v1, v2 or both, default v2");
+    println!("--format FORMAT     - print output as json (one object per line), csv, tsv or text (default)");
+    println!("--all               - print every frame of the tag with text output, with the tag version, size and padding\n");
     println!("OVERWRITE_STRING:");
     println!("Format the string in style of: ARTIST - YEAR - ALBUM / TRACK - SONGNAME");
-    println!("Please don't use - or / other than as a splitters.\n");
+    println!("Please don't use - or / other than as a splitters.");
+    println!("Discs of multi-disc sets are found from folders like \"ALBUM/CD1\" or \"ALBUM (Disc 2)\", and from numbers like \"101 - SONGNAME\",");
+    println!("and written as disc/total, counting the disc folders or numbers next to the file.");
+    println!("Tracks are written as track/total, where the total is the number of audio files in the same folder, or of the same disc.\n");
+    println!("TEMPLATE:");
+    println!("Fields %artist%, %albumartist%, %year%, %album%, %disc%, %track% and %title% are matched against the deepest path items,");
+    println!("one folder level per '/'. File extension is ignored, %% is a literal percent sign.");
+    println!("When renaming, numbers can be padded with zeros with a format like %track:02%.\n");
     println!("The other option is to separate each item for update as it's own string input, in the following order (all required):");
     println!("\"ARTIST\" \"YEAR\" \"ALBUM\" \"TRACK\" \"SONG NAME\"\n\n");
     println!("Examples:");
     println!("id3handler print \"PATH\"");
     println!("id3handler remove \"PATH\"");
+    println!("id3handler update \"DIRECTORY\" \"PATH\" \"PATH\"");
     println!("id3handler update \"PATH\"");
     println!("id3handler update \"PATH\" \"STRING AS PATH\"");
+    println!("id3handler update \"PATH\" --dry-run -l");
+    println!("id3handler rename \"PATH\" --name \"%track:02% - %title%\" --rename-dir");
+    println!("id3handler set \"PATH\" --artist \"ARTIST\" --genre \"GENRE\" --track 4");
+    println!("id3handler update \"PATH\" --compilation");
+    println!("id3handler update \"PATH\" --id3v23 --encoding utf16");
+    println!("id3handler update \"PATH\" --id3v1");
+    println!("id3handler remove \"PATH\" --tags both");
+    println!("id3handler print \"PATH\" --format json");
+    println!("id3handler copy-tags \"MASTERS\" \"MIRRORS\" --id3v23");
+    println!("id3handler organize \"INCOMING\" --library \"LIBRARY\" --copy --dry-run");
+    println!("id3handler art embed \"PATH\" --max-art-dimensions 1000x1000 --replace-art");
+    println!("id3handler update \"PATH\" --pattern \"%artist%/%year% - %album%/%track%. %title%\"");
+    println!("id3handler update \"PATH\" --regex \"(?P<artist>[^/]+)/(?P<album>[^/]+)/(?P<track>\\d+) (?P<title>[^/]+)\\.mp3$\"");
     println!("id3handler update \"PATH\" \"ARTIST\" \"YEAR\" \"ALBUM\" \"TRACK\" \"SONG NAME\"");
 }
 
@@ -639,8 +3025,24 @@ fn main() {
 
     if args.len() > 1 {
         let mut command:    String = format!("empty");
-        let mut path:       String = format!("empty");
+        let mut paths:      Vec<String> = Vec::new();
         let mut overwrite:  String = format!("empty");
+        let mut pattern:    String = String::from("empty");
+        let mut regex:      Option<Regex> = None;
+        let mut dry_run:    bool   = false;
+        let mut name:       String = String::from("%track:02% - %title%");
+        let mut dir_name:   String = String::from("empty");
+        let mut library:    String = String::from("empty");
+        let mut layout:     String = String::from("%artist%/%year% - %album%/%track:02% - %title%");
+        let mut copy:       bool   = false;
+        let mut fields:     Vec<(String, String)> = Vec::new();
+        let mut options = WriteOptions { version: Some(Version::Id3v24), convert: false, encoding: None, id3v1: false, pad_tracks: false };
+        let mut remove:     String = String::from("v2");
+        let mut output:     String = String::from("text");
+        let mut all_frames: bool   = false;
+        let mut genre_map:  Vec<(String, String)> = Vec::new();
+        let mut compilation: bool  = false;
+        let mut art = ArtOptions { names: vec![String::from("cover.*"), String::from("folder.*"), String::from("front.*")], max_size: 0, max_dimensions: None, replace: false };
         let mut verbose:    String = format!("normal");
         let mut success:    bool   = true;
         let mut count:      u8     = 0;
@@ -653,14 +3055,104 @@ fn main() {
         while let Some(arg) = args.next() {
             let scopy = format!("{}", arg);
 
-            if arg == "print" || arg == "update" || arg == "remove" {
+            if arg == "print" || arg == "update" || arg == "remove" || arg == "rename" || arg == "organize" || arg == "set" || arg == "sync-v1" || arg == "check" || arg == "copy-tags" {
                 command = format!("{}", arg);
+            } else if arg == "art" {
+                match args.next() {
+                    Some(value) if value == "embed" || value == "extract" => command = format!("art {}", value),
+                    Some(value) => { println!("Unknown art command '{}', use embed or extract! Aborting!", value); success = false; }
+                    None => { println!("Missing art command, use embed or extract! Aborting!"); success = false; }
+                }
             } else if arg == "-v" { verbose = format!("verbose");
             } else if arg == "-s" { verbose = format!("silent");
             } else if arg == "-l" { verbose = format!("loud");
             } else if arg == "-e" { verbose = format!("entry");
-            } else if path == "empty" && fs::metadata(scopy).is_ok() {
-                path = format!("{}", arg);
+            } else if arg == "--dry-run" { dry_run = true;
+            } else if arg == "--rename-dir" { dir_name = String::from("%artist% - %year% - %album%");
+            } else if arg == "--copy" { copy = true;
+            } else if arg == "--all" { all_frames = true;
+            } else if arg == "--compilation" { compilation = true;
+            } else if arg == "--pad-tracks" { options.pad_tracks = true;
+            } else if arg == "--replace-art" { art.replace = true;
+            } else if arg == "--id3v23" { options.version = Some(Version::Id3v23); options.convert = true;
+            } else if arg == "--id3v24" { options.version = Some(Version::Id3v24); options.convert = true;
+            } else if arg == "--keep-version" { options.version = None; options.convert = false;
+            } else if arg == "--id3v1" { options.id3v1 = true;
+            } else if arg == "--tags" {
+                match args.next() {
+                    Some(value) if value == "v1" || value == "v2" || value == "both" => remove = value,
+                    Some(value) => { println!("Unknown tags '{}', use v1, v2 or both! Aborting!", value); success = false; }
+                    None => { println!("Missing value for --tags! Aborting!"); success = false; }
+                }
+            } else if arg == "--encoding" {
+                match args.next().map(|value| (parse_encoding(&value), value)) {
+                    Some((Some(value), _)) => options.encoding = Some(value),
+                    Some((None, value)) => { println!("Unknown encoding '{}', use latin1, utf16, utf16be or utf8! Aborting!", value); success = false; }
+                    None => { println!("Missing value for --encoding! Aborting!"); success = false; }
+                }
+            } else if arg == "--format" {
+                match args.next() {
+                    Some(value) if value == "text" || value == "json" || value == "csv" || value == "tsv" => output = value,
+                    Some(value) => { println!("Unknown format '{}', use json, csv, tsv or text! Aborting!", value); success = false; }
+                    None => { println!("Missing value for --format! Aborting!"); success = false; }
+                }
+            } else if arg == "--name" || arg == "--dir-name" || arg == "--layout" {
+                match args.next() {
+                    Some(value) if arg == "--name" => name = value,
+                    Some(value) if arg == "--layout" => layout = value,
+                    Some(value) => dir_name = value,
+                    None => { println!("Missing template for {}! Aborting!", arg); success = false; }
+                }
+            } else if arg.starts_with("--") && SET_FIELDS.contains(&&arg[2..]) {
+                match args.next() {
+                    Some(value) => match validate_field(&arg[2..], &value) {
+                        Ok(()) => fields.push((String::from(&arg[2..]), value)),
+                        Err(err) => { println!("{}! Aborting!", err); success = false; }
+                    },
+                    None => { println!("Missing value for {}! Aborting!", arg); success = false; }
+                }
+            } else if arg == "--genre-map" {
+                match args.next().map(|value| read_genre_map(&value)) {
+                    Some(Ok(value)) => genre_map = value,
+                    Some(Err(err)) => { println!("{}! Aborting!", err); success = false; }
+                    None => { println!("Missing file for --genre-map! Aborting!"); success = false; }
+                }
+            } else if arg == "--art-names" {
+                match args.next() {
+                    Some(value) if !value.trim().is_empty() => art.names = value.split(',').map(|name| String::from(name.trim())).filter(|name| !name.is_empty()).collect(),
+                    Some(_) => { println!("Empty list for --art-names! Aborting!"); success = false; }
+                    None => { println!("Missing list for --art-names! Aborting!"); success = false; }
+                }
+            } else if arg == "--max-art-size" {
+                match args.next().map(|value| (parse_size(&value), value)) {
+                    Some((Some(value), _)) => art.max_size = value,
+                    Some((None, value)) => { println!("Invalid size '{}', give it like 500K or 2M! Aborting!", value); success = false; }
+                    None => { println!("Missing size for --max-art-size! Aborting!"); success = false; }
+                }
+            } else if arg == "--max-art-dimensions" {
+                match args.next().map(|value| (value.split_once(['x', 'X']).and_then(|(width, height)| Some((width.trim().parse::<u32>().ok()?, height.trim().parse::<u32>().ok()?))), value)) {
+                    Some((Some(value), _)) => art.max_dimensions = Some(value),
+                    Some((None, value)) => { println!("Invalid dimensions '{}', give them like 600x600! Aborting!", value); success = false; }
+                    None => { println!("Missing dimensions for --max-art-dimensions! Aborting!"); success = false; }
+                }
+            } else if arg == "--library" {
+                match args.next() {
+                    Some(value) => library = value,
+                    None => { println!("Missing directory for --library! Aborting!"); success = false; }
+                }
+            } else if arg == "--pattern" {
+                match args.next() {
+                    Some(value) => pattern = value,
+                    None => { println!("Missing template for --pattern! Aborting!"); success = false; }
+                }
+            } else if arg == "--regex" {
+                match args.next().map(|value| Regex::new(&value)) {
+                    Some(Ok(value)) => regex = Some(value),
+                    Some(Err(err)) => { println!("Invalid regex: {}", err); success = false; }
+                    None => { println!("Missing expression for --regex! Aborting!"); success = false; }
+                }
+            } else if fs::metadata(scopy).is_ok() {
+                paths.push(arg);
             } else if arg.contains("/") {
                 overwrite = format!("{}", arg);
             } else {
@@ -682,32 +3174,148 @@ fn main() {
             }
         }
 
-        if fs::metadata(&path).is_ok() && success {
-            let tag_data: ID3TagInfo = ID3TagInfo::read(&path);
+        if count == 5 {
+            for (name, value) in [("year", &year), ("track", &track)] {
+                if let Err(err) = validate_field(name, value) { println!("{}! Aborting!", err); success = false; }
+            }
+        }
 
-            if verbose == "loud" || verbose == "entry" { println!("Handling '{}'", path); }
+        if regex.is_some() && pattern != "empty" {
+            println!("--regex and --pattern can't be used together, give only one of them! Aborting!");
+            success = false;
+        }
 
-            if command == "print" {
-                print_tag(&tag_data, &path);
-            } else if command == "update" {
-                if count > 0 {
-                    let write_tag: ID3TagInfo = ID3TagInfo::force(&artist, &year, &album, &track, &title);
-                    write_tags(&path, &write_tag, &tag_data, &verbose);
-                } else if overwrite != "empty" {
-                    let write_tag: ID3TagInfo = ID3TagInfo::parse(&overwrite);
-                    write_tags(&path, &write_tag, &tag_data, &verbose);
-                } else {
-                    let write_tag: ID3TagInfo = ID3TagInfo::parse(&path);
-                    write_tags(&path, &write_tag, &tag_data, &verbose);
-                }
-            } else if command == "remove" {
-                remove_tag(&path, &tag_data, &verbose);
-            } else {
+        if !paths.is_empty() && success {
+            if command != "print" && command != "update" && command != "remove" && command != "rename" && command != "organize" && command != "set" && command != "sync-v1" && command != "check" && command != "copy-tags" && command != "art embed" && command != "art extract" {
                 println!("Unknown or failed command {}", command);
                 print_help();
+                return;
+            }
+
+            if command == "set" && fields.is_empty() && !compilation {
+                println!("No items given for set, give them like --artist \"ARTIST\"! Aborting!");
+                return;
+            }
+
+            if command == "copy-tags" && paths.len() != 2 {
+                println!("copy-tags needs a source and a target path! Aborting!");
+                return;
+            }
+
+            if command == "organize" && library == "empty" {
+                println!("Library root is required for organize, give it with --library! Aborting!");
+                return;
+            }
+
+            let mut files: Vec<String> = Vec::new();
+            let mut summary = BatchSummary::new();
+            let name_tokens: Vec<PatternToken> = parse_pattern_tokens(&name);
+            let dir_tokens: Vec<PatternToken> = parse_pattern_tokens(&dir_name);
+            let library_info = LibraryInfo { root: library.clone(), layout: parse_pattern_tokens(&layout), copy };
+            let mut renamed: Vec<PathBuf> = Vec::new();
+            let mut dirs: Vec<(PathBuf, String)> = Vec::new();
+            let mut extracted: Vec<PathBuf> = Vec::new();
+
+            // Files of copy-tags are the targets, the source of each is found from the pairs
+            let pairs: Vec<(String, String)> = if command == "copy-tags" { pair_files(&paths[0], &paths[1], &verbose) } else { Vec::new() };
+
+            if command == "copy-tags" {
+                files = pairs.iter().map(|(_, target)| target.clone()).collect();
+            } else {
+                let mut visited: Vec<PathBuf> = Vec::new();
+
+                for path in &paths {
+                    collect_files(path, &mut files, &mut visited);
+                }
+            }
+
+            if command == "print" { print_record_header(&output); }
+
+            for path in &files {
+                // Format is detected once, as it reads the start of the file
+                let content = sniff_format(path);
+                let format = content.or_else(|| extension_format(path)).unwrap_or("mp3");
+                let tag_data: ID3TagInfo = ID3TagInfo::read(path, format);
+
+                if verbose == "loud" || verbose == "entry" { println!("Handling '{}'", path); }
+
+                if command == "print" && output != "text" {
+                    print_record(&tag_data, path, format, &output);
+                } else if command == "print" {
+                    print_tag(&tag_data, path, format);
+                    if all_frames { print_frames(path, format); }
+                } else if command == "update" {
+                    let mut write_tag: ID3TagInfo = if count > 0 {
+                        ID3TagInfo::force(&artist, &year, &album, &track, &title)
+                    } else if let Some(expression) = &regex {
+                        let source: &str = if overwrite != "empty" { &overwrite } else { path };
+                        ID3TagInfo::parse_regex(source, expression)
+                    } else if compilation && pattern == "empty" {
+                        ID3TagInfo::parse_compilation(if overwrite != "empty" { &overwrite } else { path })
+                    } else if overwrite != "empty" {
+                        ID3TagInfo::parse(&overwrite, &pattern)
+                    } else {
+                        ID3TagInfo::parse(path, &pattern)
+                    };
+
+                    // Items given as options override the parsed ones
+                    for (name, value) in &fields {
+                        write_tag.set_field(name, value);
+                    }
+
+                    if compilation { write_tag.compilation = true; }
+
+                    write_tag.map_genre(&tag_data, &genre_map);
+                    summary.add(write_tags(path, format, &write_tag, &tag_data, &options, &verbose, dry_run));
+                } else if command == "rename" {
+                    summary.add(rename_file(path, &tag_data, &name_tokens, &mut renamed, &verbose, dry_run));
+
+                    if dir_name != "empty" {
+                        let parent = Path::new(path).parent().map(|dir| dir.to_path_buf()).unwrap_or_default();
+
+                        match render_pattern(&dir_tokens, &tag_data) {
+                            Ok(value) if parent.file_name().is_some() => dirs.push((parent, value)),
+                            Ok(_) => {}
+                            Err(field) => if verbose != "entry" { println!("No '{}' in tag data, can't rename directory of '{}'", field, path); }
+                        }
+                    }
+                } else if command == "set" {
+                    // Only the given items are written, the rest of the tag is left as it is
+                    let mut write_tag: ID3TagInfo = ID3TagInfo::empty();
+
+                    for (name, value) in &fields {
+                        write_tag.set_field(name, value);
+                    }
+
+                    if compilation { write_tag.compilation = true; }
+
+                    write_tag.map_genre(&tag_data, &genre_map);
+                    summary.add(write_tags(path, format, &write_tag, &tag_data, &options, &verbose, dry_run));
+                } else if command == "organize" {
+                    summary.add(organize_file(path, &tag_data, &library_info, &mut renamed, &verbose, dry_run));
+                } else if command == "sync-v1" {
+                    summary.add(sync_v1(path, format, &verbose, dry_run));
+                } else if command == "check" {
+                    summary.add(check_file(path, content, &verbose));
+                } else if command == "art embed" {
+                    summary.add(embed_art(path, format, &art, &options, &verbose, dry_run));
+                } else if command == "art extract" {
+                    summary.add(extract_art(path, format, &art, &mut extracted, &verbose, dry_run));
+                } else if command == "copy-tags" {
+                    if let Some((source, _)) = pairs.iter().find(|(_, target)| target == path) {
+                        summary.add(copy_tags(source, path, format, &tag_data, &options, &verbose, dry_run));
+                    }
+                } else {
+                    summary.add(remove_tag(path, format, &remove, &verbose, dry_run));
+                }
             }
+
+            if !dirs.is_empty() { rename_dirs(&dirs, &verbose, dry_run); }
+
+            if command != "print" && files.len() > 1 && verbose != "silent" { summary.print(&command); }
+            if command != "print" && dry_run && verbose != "silent" { println!("Dry run, no files were changed."); }
         } else {
-            if success { println!("File doesn't exists: '{}'", path); }
+            if success { println!("File doesn't exists: '{}'", paths.join("', '")); }
             print_help();
         }
 
@@ -717,3 +3325,650 @@ fn main() {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use id3::frame::Comment;
+
+    #[test]
+    fn parse_accented_path() {
+        let info = ID3TagInfo::parse("Björk - 1997 - Homogenic/03 - Jóga.mp3", "empty");
+
+        assert_eq!(info.artist, "Björk");
+        assert_eq!(info.year, 1997);
+        assert_eq!(info.album, "Homogenic");
+        assert_eq!(info.track, 3);
+        assert_eq!(info.title, "Jóga");
+    }
+
+    #[test]
+    fn parse_cjk_path() {
+        let info = ID3TagInfo::parse("music/宇多田ヒカル - 1999 - First Love/07 - 甘いワナ.mp3", "empty");
+
+        assert_eq!(info.artist, "宇多田ヒカル");
+        assert_eq!(info.year, 1999);
+        assert_eq!(info.album, "First Love");
+        assert_eq!(info.track, 7);
+        assert_eq!(info.title, "甘いワナ");
+    }
+
+    #[test]
+    fn parse_emoji_path() {
+        let info = ID3TagInfo::parse("🎸 Band - 2015 - 🔥 Hits/12 - Song 🎵.mp3", "empty");
+
+        assert_eq!(info.artist, "🎸 Band");
+        assert_eq!(info.year, 2015);
+        assert_eq!(info.album, "🔥 Hits");
+        assert_eq!(info.track, 12);
+        assert_eq!(info.title, "Song 🎵");
+    }
+
+    #[test]
+    fn parse_non_ascii_numerals() {
+        // '²' and '三' are numeric chars, and must not be taken as part of a number
+        let album = AlbumInfo::parse("Ensemble ² - 2003 - 三重奏/01 - Intro.mp3");
+        let track = TrackInfo::parse("Ensemble ² - 2003 - 三重奏/三 - ²³ Intro.mp3");
+
+        assert_eq!(album.artist, "Ensemble ²");
+        assert_eq!(album.year, 2003);
+        assert_eq!(album.album, "三重奏");
+        assert_eq!(track.track, 0);
+        assert_eq!(track.title, "²³ Intro");
+    }
+
+    #[test]
+    fn string_helpers_multibyte() {
+        assert_eq!(find_first_char("Jóga - ß", '-'), 6);
+        assert_eq!(find_last_char("é/ü/ö", '/'), 5);
+        assert_eq!(get_char_count("ä-ö-ü", '-'), 2);
+        assert_eq!(find_number("日本 2001 -", 0, 4), 7);
+        assert_eq!(find_number("ñ²³ x", 0, 2), -1);
+        assert_eq!(get_string_between("日本語", 3, 6), "本");
+        assert_eq!(get_string_between("日本語", 1, 6), "");
+        assert_eq!(remove_whitespace(" - Sigur Rós _"), "Sigur Rós");
+        assert_eq!(remove_whitespace(""), "");
+    }
+
+    #[test]
+    fn render_rename_template() {
+        let mut info = ID3TagInfo::empty();
+        info.set_field("track", "7");
+        info.set_field("title", "What? / Why: \"Now\"...");
+
+        let name = render_pattern(&parse_pattern_tokens("%track:02% - %title%"), &info);
+        let missing = render_pattern(&parse_pattern_tokens("%artist% - %title%"), &info);
+
+        assert_eq!(name, Ok(String::from("07 - What_ _ Why_ _Now_")));
+        assert_eq!(missing, Err(String::from("artist")));
+    }
+
+    #[test]
+    fn convert_recording_time() {
+        let mut tag = Tag::new();
+        tag.set_date_recorded(Timestamp { year: 1997, month: Some(9), day: Some(22), hour: Some(18), minute: Some(5), second: None });
+
+        let v23 = convert_tag(&tag, Version::Id3v23, None);
+        assert_eq!(v23.year(), Some(1997));
+        assert_eq!(v23.text_for_frame_id("TDAT"), Some("2209"));
+        assert_eq!(v23.text_for_frame_id("TIME"), Some("1805"));
+        assert!(v23.get("TDRC").is_none());
+
+        let v24 = convert_tag(&v23, Version::Id3v24, None);
+        assert_eq!(v24.date_recorded(), tag.date_recorded());
+        assert!(v24.get("TYER").is_none() && v24.get("TDAT").is_none() && v24.get("TIME").is_none());
+    }
+
+    #[test]
+    fn vorbis_comment_mapping() {
+        let fields: Vec<(String, String)> = vec![
+            (String::from("ARTIST"), String::from("Björk")), (String::from("TRACKNUMBER"), String::from("3")),
+            (String::from("TRACKTOTAL"), String::from("11")), (String::from("DATE"), String::from("1997")),
+            (String::from("REPLAYGAIN_TRACK_GAIN"), String::from("-3.2 dB")),
+        ];
+
+        let tag = vorbis::to_tag(&fields, &[]);
+        assert_eq!(tag.artist(), Some("Björk"));
+        assert_eq!(tag.track(), Some(3));
+        assert_eq!(tag.total_tracks(), Some(11));
+        assert_eq!(tag.date_recorded().map(|date| date.year), Some(1997));
+
+        let (mut back, pictures) = vorbis::from_tag(&tag);
+        back.sort();
+        let mut expected = fields.clone();
+        expected.sort();
+        assert_eq!(back, expected);
+        assert!(pictures.is_empty());
+    }
+
+    #[test]
+    fn describe_frame_content() {
+        let text = Content::Text(String::from("Björk\0Sugarcubes"));
+        assert_eq!(describe_content(&text), "'Björk' / 'Sugarcubes'");
+
+        let comment = Content::Comment(Comment { lang: String::from("eng"), description: String::from("note"), text: String::from("live") });
+        assert_eq!(describe_content(&comment), "[eng] 'note' = 'live'");
+
+        let picture = Content::Picture(Picture {
+            mime_type: String::from("image/png"), picture_type: PictureType::CoverFront, description: String::new(), data: vec![0u8; 42],
+        });
+        assert_eq!(describe_content(&picture), "Front cover (3), image/png, 42 bytes, ''");
+    }
+
+    #[test]
+    fn genre_references() {
+        assert_eq!(parse_genre("(17)"), "Rock");
+        assert_eq!(parse_genre("(4)Eurodisco"), "Eurodisco");
+        assert_eq!(parse_genre("(RX)(31)"), "Remix / Trance");
+        assert_eq!(parse_genre("((Unknown) Style"), "(Unknown) Style");
+        assert_eq!(parse_genre("7\0Jazz"), "Hip-Hop / Jazz");
+        assert_eq!(parse_genre("(999)"), "(999)");
+
+        let map = vec![(String::from("hip hop"), String::from("Hip-Hop")), (String::from("hiphop"), String::from("Hip-Hop"))];
+        assert_eq!(normalize_genre("HipHop / Jazz", &map), "Hip-Hop / Jazz");
+        assert_eq!(normalize_genre("Hip Hop", &map), "Hip-Hop");
+        assert_eq!(id3v1::genre_index("hip-hop"), Some(7));
+    }
+
+    #[test]
+    fn parse_compilation_path() {
+        let info = ID3TagInfo::parse_compilation("Various Artists - 2003 - Hits/03 - Björk - Jóga.mp3");
+
+        assert_eq!(info.album_artist, "Various Artists");
+        assert_eq!(info.artist, "Björk");
+        assert_eq!(info.title, "Jóga");
+        assert_eq!(info.album, "Hits");
+        assert_eq!(info.track, 3);
+        assert!(info.compilation);
+
+        let single = ID3TagInfo::parse_compilation("Various Artists - 2003 - Hits/04 - Interlude.mp3");
+        assert_eq!(single.artist, "empty");
+        assert_eq!(single.title, "Interlude");
+    }
+
+    #[test]
+    fn parse_disc_paths() {
+        assert_eq!(parse_disc("CD1"), Some((String::from(""), 1)));
+        assert_eq!(parse_disc("Homogenic (Disc 2)"), Some((String::from("Homogenic"), 2)));
+        assert_eq!(parse_disc("Homogenic [cd 3]"), Some((String::from("Homogenic"), 3)));
+        assert_eq!(parse_disc("ABCD1"), None);
+        assert_eq!(parse_disc("Homogenic"), None);
+
+        let album = AlbumInfo::parse("Björk - 1997 - Homogenic/CD2/03 - Jóga.mp3");
+        assert_eq!((album.artist.as_str(), album.album.as_str(), album.disc), ("Björk", "Homogenic", 2));
+
+        let album = AlbumInfo::parse("Björk - 1997 - Homogenic (Disc 2)/03 - Jóga.mp3");
+        assert_eq!((album.album.as_str(), album.disc), ("Homogenic", 2));
+
+        let track = TrackInfo::parse("Björk - 1997 - Homogenic/203 - Jóga.mp3");
+        assert_eq!((track.title.as_str(), track.track, track.disc), ("Jóga", 3, 2));
+    }
+
+    #[test]
+    fn track_numbers_and_padding() {
+        let track = TrackInfo::parse("Reader - 2010 - Book/100 - Part.mp3");
+        assert_eq!((track.track, track.disc), (100, 0));
+
+        // Four digits are more likely a year or a part of the title
+        assert_eq!(TrackInfo::parse("Reader - 2010 - Book/1999 - Part.mp3").track, 0);
+        assert_eq!(TrackInfo::parse("Kubrick - 1968 - Films/2001 A Space Odyssey.mp3").track, 0);
+
+        let mut info = ID3TagInfo::empty();
+        info.set_field("track", "7/120");
+        assert_eq!((info.track, info.track_total), (7, 120));
+
+        let mut tag = Tag::new();
+        tag.set_text("TRCK", "7/120");
+        assert!(pad_track(&mut tag));
+        assert_eq!(tag.text_for_frame_id("TRCK"), Some("007/120"));
+        assert!(!pad_track(&mut tag));
+
+        tag.set_text("TRCK", "3");
+        assert!(pad_track(&mut tag));
+        assert_eq!(tag.text_for_frame_id("TRCK"), Some("03"));
+    }
+
+    #[test]
+    fn image_headers_and_limits() {
+        let png: Vec<u8> = [&b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR"[..], &640u32.to_be_bytes(), &480u32.to_be_bytes()].concat();
+        assert_eq!(image::mime_type(&png), Some("image/png"));
+        assert_eq!(image::dimensions(&png), Some((640, 480)));
+
+        // APP0 segment before the baseline start of frame, which holds height before width
+        let jpeg: Vec<u8> = vec![0xff, 0xd8, 0xff, 0xe0, 0x00, 0x04, 0x00, 0x00, 0xff, 0xff, 0xc0, 0x00, 0x11, 0x08, 0x01, 0xf4, 0x03, 0xe8];
+        assert_eq!(image::mime_type(&jpeg), Some("image/jpeg"));
+        assert_eq!(image::dimensions(&jpeg), Some((1000, 500)));
+        assert_eq!(image::dimensions(&jpeg[..12]), None);
+        assert_eq!(image::extension("image/jpeg"), "jpg");
+
+        assert_eq!(parse_size("500K"), Some(512000));
+        assert_eq!(parse_size("2m"), Some(2097152));
+        assert_eq!(parse_size("big"), None);
+        assert_eq!(parse_size("18446744073709551615M"), None);
+    }
+
+    #[test]
+    fn sniff_audio_content() {
+        // MPEG-1 layer III, 128 kbit/s, 44.1 kHz, so frames are 417 bytes long
+        let frame: Vec<u8> = [&[0xff, 0xfb, 0x90, 0x00][..], &[0u8; 413]].concat();
+        assert_eq!(mpeg_frame_length(&frame), Some(417));
+        assert_eq!(sniff_data(&frame.repeat(2)), Some("mp3"));
+        assert_eq!(sniff_data(&frame), None);
+
+        // UTF-16LE byte order mark looks like a frame sync
+        let text: Vec<u8> = [&[0xff, 0xfe][..], &"Liner notes".encode_utf16().flat_map(|c| c.to_le_bytes()).collect::<Vec<u8>>()].concat();
+        assert_eq!(sniff_data(&text), None);
+        assert_eq!(mpeg_frame_length(&[0xff, 0xfb, 0xf0, 0x00]), None);
+        assert_eq!(mpeg_frame_length(&[0xff, 0xfb, 0x9c, 0x00]), None);
+
+        assert_eq!(sniff_data(b"fLaC\0\0\0\x22"), Some("flac"));
+        assert_eq!(sniff_data(b"\0\0\0\x20ftypM4A "), Some("mp4"));
+        assert_eq!(sniff_data(b"RIFF\0\0\0\0WAVE"), Some("wav"));
+        assert_eq!(sniff_data(b"OggS"), Some("ogg"));
+
+        assert!(is_audio_file(Path::new("album/01 - Song.MP3")));
+        assert!(!is_audio_file(Path::new("album/01 - notes.txt")));
+
+        // MPEG stream saved with a wrong extension is found from its content
+        let path = std::env::temp_dir().join(format!("id3handler-sniff-{}.bin", std::process::id()));
+        fs::write(&path, frame.repeat(2)).unwrap();
+        let found = is_audio_file(&path);
+        fs::write(&path, &text).unwrap();
+        let text_found = is_audio_file(&path);
+        let _ = fs::remove_file(&path);
+        assert!(found && !text_found);
+    }
+
+    #[test]
+    fn record_escaping() {
+        let mut tag = Tag::new();
+        tag.set_artist("AC_DC -");
+        tag.set_album("Back\\Slash");
+        tag.set_title("Say \"hi\", twice");
+        tag.add_frame(ExtendedText { description: String::from("Mood"), value: String::from("a\tb") });
+
+        let info = ID3TagInfo::from_tag(&tag, "ID3v2.4");
+        assert_eq!((info.artist.as_str(), info.album.as_str(), info.title.as_str()), ("AC_DC -", "Back\\Slash", "Say \"hi\", twice"));
+
+        let json = format_record(&info, "a.mp3", "mp3", "json");
+        assert!(json.contains(r#""artist":"AC_DC -","year":null,"album":"Back\\Slash","track":null,"title":"Say \"hi\", twice""#));
+        assert!(json.ends_with(r#""extra":{"Mood":"a\tb"}}"#));
+        assert_eq!(json_string("\u{1}"), "\"\\u0001\"");
+
+        let csv = format_record(&info, "a.mp3", "mp3", "csv");
+        assert_eq!(csv, r#"a.mp3,MP3,ID3v2.4,AC_DC -,,Back\Slash,,"Say ""hi"", twice",,,false,,,"#);
+
+        let tsv = format_record(&info, "a.mp3", "mp3", "tsv");
+        assert_eq!(tsv.split('\t').nth(5), Some("Back\\\\Slash"));
+    }
+
+    #[test]
+    fn riff_info_and_tag_chunks() {
+        let chunk = |id: &[u8], data: &[u8]| -> Vec<u8> { [id, &(data.len() as u32).to_le_bytes(), data, if data.len() % 2 == 1 { &[0u8][..] } else { &[] }].concat() };
+        let info = [&b"INFO"[..], &chunk(b"IART", b"Artist\0"), &chunk(b"ICRD", b"2012-05-01\0"), &chunk(b"ICMT", b"Notes\0")].concat();
+        let body = [&b"WAVE"[..], &chunk(b"fmt ", &[0u8; 16]), &chunk(b"LIST", &info), &chunk(b"ID3 ", b"ID3"), &chunk(b"data", &[1, 2, 3])].concat();
+        let wav = [&b"RIFF"[..], &(body.len() as u32).to_le_bytes(), &body].concat();
+
+        let tag = riff::parse_info(&wav).unwrap();
+        assert_eq!((tag.artist(), tag.year()), (Some("Artist"), Some(2012)));
+        assert_eq!(tag.comments().next().map(|comment| comment.text.as_str()), Some("Notes"));
+
+        // Tag chunks are dropped, audio keeps its padding byte and the RIFF size is fixed
+        let stripped = riff::strip_tags(&wav).unwrap().unwrap();
+        let expected = [&b"WAVE"[..], &chunk(b"fmt ", &[0u8; 16]), &chunk(b"data", &[1, 2, 3])].concat();
+        assert_eq!(stripped, [&b"RIFF"[..], &(expected.len() as u32).to_le_bytes(), &expected].concat());
+        assert!(riff::strip_tags(&stripped).unwrap().is_none());
+        assert!(riff::parse_info(&stripped).is_err());
+
+        // INFO list declaring less than its type is an error, not a panic
+        let broken = [&b"RIFF"[..], &[18, 0, 0, 0], b"WAVE", b"LIST", &[2, 0, 0, 0], b"INFO\0\0"].concat();
+        assert!(riff::parse_info(&broken).is_err());
+    }
+
+    #[test]
+    fn mp4_items_and_offsets() {
+        let atom = |kind: &[u8], data: &[u8]| -> Vec<u8> { [&(data.len() as u32 + 8).to_be_bytes()[..], kind, data].concat() };
+        let build = |offset: u32, extra: &[u8]| -> Vec<u8> {
+            let stco = atom(b"stco", &[&[0u8; 4][..], &1u32.to_be_bytes(), &offset.to_be_bytes()].concat());
+            let trak = atom(b"trak", &atom(b"mdia", &atom(b"minf", &atom(b"stbl", &stco))));
+            let moov = atom(b"moov", &[&trak[..], extra].concat());
+            [&atom(b"ftyp", b"M4A \0\0\0\0")[..], &moov, &atom(b"mdat", b"AUDIO")].concat()
+        };
+
+        let original = build(0, &[]);
+        let audio = original.len() as u32 - 5;
+        let original = build(audio, &[]);
+
+        let mut tag = Tag::new();
+        tag.set_title("Porcelain");
+        tag.set_artist("Moby");
+        tag.set_text("TRCK", "4/12");
+        tag.set_text("TCMP", "1");
+
+        let output = mp4::rebuild(&original, &tag).unwrap();
+        let read = mp4::read_from(&mut std::io::Cursor::new(&output)).unwrap();
+        assert_eq!((read.title(), read.artist(), read.track(), read.total_tracks()), (Some("Porcelain"), Some("Moby"), Some(4), Some(12)));
+        assert_eq!(read.get("TCMP").and_then(|frame| frame.content().text()), Some("1"));
+
+        // Chunk offset follows the audio data after the grown moov atom
+        let pos = output.windows(4).position(|kind| kind == b"stco").unwrap() + 12;
+        let offset = u32::from_be_bytes(output[pos..pos + 4].try_into().unwrap()) as usize;
+        assert!(offset > audio as usize);
+        assert_eq!(&output[offset..offset + 5], b"AUDIO");
+
+        // Removing the items moves the audio back
+        let removed = mp4::rebuild(&output, &Tag::new()).unwrap();
+        assert!(mp4::read_from(&mut std::io::Cursor::new(&removed)).unwrap().title().is_none());
+        assert_eq!(&removed[removed.len() - 5..], b"AUDIO");
+
+        // Audio split around the moov atom, only the offsets after it move
+        let stco = |offsets: &[u32]| -> Vec<u8> {
+            let table = atom(b"stco", &[&[0u8; 4][..], &(offsets.len() as u32).to_be_bytes(), &offsets.iter().flat_map(|offset| offset.to_be_bytes()).collect::<Vec<u8>>()].concat());
+            atom(b"moov", &atom(b"trak", &atom(b"mdia", &atom(b"minf", &atom(b"stbl", &table)))))
+        };
+        let head = [&atom(b"ftyp", b"M4A \0\0\0\0")[..], &atom(b"mdat", b"FIRST")].concat();
+        let first = head.len() as u32 - 5;
+        let second = (head.len() + stco(&[0, 0]).len() + 8) as u32;
+        let split = [&head[..], &stco(&[first, second]), &atom(b"mdat", b"AUDIO")].concat();
+
+        let output = mp4::rebuild(&split, &tag).unwrap();
+        let pos = output.windows(4).position(|kind| kind == b"stco").unwrap() + 12;
+        let offsets: Vec<usize> = output[pos..pos + 8].chunks(4).map(|entry| u32::from_be_bytes(entry.try_into().unwrap()) as usize).collect();
+        assert_eq!(offsets[0], first as usize);
+        assert_eq!((&output[offsets[0]..offsets[0] + 5], &output[offsets[1]..offsets[1] + 5]), (&b"FIRST"[..], &b"AUDIO"[..]));
+
+        // 32-bit offsets that would overflow and fragmented files are not written
+        assert!(mp4::rebuild(&build(u32::MAX - 4, &[]), &tag).is_err());
+        assert!(mp4::rebuild(&build(audio + 8, &atom(b"mvex", &[])), &tag).is_err());
+    }
+
+    #[test]
+    fn copy_pairs_and_frames() {
+        let sources = vec![String::from("src/Album/01 - One.flac"), String::from("src/Album/02 - Two.flac"), String::from("src/Other/05 - Five.flac")];
+        let targets = vec![String::from("dst/Album/02 - Two (Remaster).mp3"), String::from("dst/Album/01 - One.mp3"), String::from("dst/Album/05 - Five.mp3")];
+        let pairs = match_files("src", sources, "dst", targets, "silent");
+
+        // Same path is matched first, then the same track number in the same folder
+        assert_eq!(pairs, vec![
+            (String::from("src/Album/01 - One.flac"), String::from("dst/Album/01 - One.mp3")),
+            (String::from("src/Album/02 - Two.flac"), String::from("dst/Album/02 - Two (Remaster).mp3")),
+        ]);
+
+        assert!(is_copied_frame("TCON") && is_copied_frame("APIC") && is_copied_frame("COMM"));
+        assert!(!is_copied_frame("PRIV") && !is_copied_frame("UFID"));
+
+        let mut first = Tag::new();
+        first.set_title("One");
+        first.add_frame(Comment { lang: String::from("eng"), description: String::new(), text: String::from("note") });
+        let mut second = Tag::new();
+        second.add_frame(Comment { lang: String::from("eng"), description: String::new(), text: String::from("note") });
+        second.set_title("One");
+        assert!(same_frames(&first, &second));
+
+        second.add_frame(Picture { mime_type: String::from("image/png"), picture_type: PictureType::CoverFront, description: String::new(), data: vec![1, 2, 3] });
+        assert!(!same_frames(&first, &second));
+    }
+
+    #[test]
+    fn parse_regex_groups() {
+        let regex = Regex::new(r"(?P<artist>[^/]+)/(?P<Year>\d{4}) - (?P<album>[^/]+)/(?P<track>\d+) (?P<title>[^/]+) \[(?P<Source>[^\]]+)\]\.mp3$").unwrap();
+        let info = ID3TagInfo::parse_regex("music/Björk/1997 - Homogenic/03 Jóga [Vinyl].mp3", &regex);
+
+        // Known names are matched ignoring case, other groups become TXXX frames
+        assert_eq!((info.artist.as_str(), info.year, info.album.as_str(), info.track, info.title.as_str()), ("Björk", 1997, "Homogenic", 3, "Jóga"));
+        assert_eq!(info.extra, vec![(String::from("Source"), String::from("Vinyl"))]);
+
+        let mut tag = Tag::new();
+        let report = update_tag(&mut tag, &info);
+        assert_eq!(tag.extended_texts().next().map(|text| (text.description.as_str(), text.value.as_str())), Some(("Source", "Vinyl")));
+        assert!(report.added.contains(&String::from("TXXX:Source")));
+
+        let info = ID3TagInfo::parse_regex("music/Björk/Homogenic/Jóga.mp3", &regex);
+        assert!(is_empty(&info) && info.extra.is_empty());
+    }
+
+    #[test]
+    fn parse_path_templates() {
+        let tokens = parse_pattern_tokens("%Track:02%. %title% 100%%");
+        assert!(matches!(&tokens[0], PatternToken::Field(name, format) if name == "track" && format == "02"));
+        assert!(matches!(&tokens[3], PatternToken::Text(text) if text == " 100%"));
+
+        let info = ID3TagInfo::parse_pattern("music/Björk/1997 - Homogenic/03. Jóga.mp3", "%artist%/%year% - %album%/%track%. %title%");
+        assert_eq!((info.artist.as_str(), info.year, info.album.as_str(), info.track, info.title.as_str()), ("Björk", 1997, "Homogenic", 3, "Jóga"));
+
+        // Fields are tried shortest first, and numbers take only digits
+        let mut fields: Vec<(String, String)> = Vec::new();
+        assert!(match_pattern(&parse_pattern_tokens("%title% - %artist%"), "A - B - C", &mut fields));
+        assert_eq!(fields, vec![(String::from("title"), String::from("A")), (String::from("artist"), String::from("B - C"))]);
+
+        fields.clear();
+        assert!(!match_pattern(&parse_pattern_tokens("%track% %title%"), "x1 Song", &mut fields));
+        assert!(fields.is_empty());
+
+        let info = ID3TagInfo::parse_pattern("Björk/Homogenic/Jóga.mp3", "%artist%/%year% - %album%/%title%");
+        assert!(is_empty(&info));
+    }
+
+    #[test]
+    fn batch_summary_counts() {
+        let mut summary = BatchSummary::new();
+        for status in [FileStatus::Updated, FileStatus::Skipped, FileStatus::Updated, FileStatus::Failed] {
+            summary.add(status);
+        }
+
+        assert_eq!((summary.updated, summary.skipped, summary.failed), (2, 1, 1));
+        assert_eq!(summary.line("update"), "Updated: 2, skipped (already matching): 1, failed: 1");
+        assert_eq!(summary.line("remove"), "Removed: 2, skipped (already empty): 1, failed: 1");
+        assert_eq!(summary.line("art embed"), "Embedded: 2, skipped (already embedded): 1, failed: 1");
+    }
+
+    #[test]
+    fn id3v1_fields() {
+        let mut tag = Tag::new();
+        tag.set_title("Ωmega Jóga and a title that is longer than thirty bytes");
+        tag.set_artist("Björk");
+        tag.set_album("Homogenic");
+        tag.set_year(1997);
+        tag.set_track(3);
+        tag.set_genre("Hip-Hop");
+        tag.add_frame(Comment { lang: String::from("eng"), description: String::new(), text: "c".repeat(40) });
+
+        let data = id3v1::build(&tag, 255);
+        assert_eq!(&data[0..3], b"TAG");
+        assert_eq!(&data[3..33], b"?mega J\xf3ga and a title that is");
+        assert_eq!(&data[33..40], b"Bj\xf6rk\0\0");
+        assert_eq!(&data[93..97], b"1997");
+        assert_eq!(&data[97..125], "c".repeat(28).as_bytes());
+        assert_eq!((data[125], data[126], data[127]), (0, 3, 7));
+
+        // Without a track the comment takes the whole field, unknown genres use the given index
+        tag.remove_track();
+        tag.set_genre("Not a genre");
+        let data = id3v1::build(&tag, 12);
+        assert_eq!(&data[97..127], "c".repeat(30).as_bytes());
+        assert_eq!(data[127], 12);
+    }
+
+    #[test]
+    fn ogg_pages_and_packets() {
+        let crc = |bytes: &[u8]| bytes.iter().fold(0u32, |crc, byte| {
+            (0..8).fold(crc ^ (*byte as u32) << 24, |crc, _| if crc & 0x8000_0000 != 0 { crc << 1 ^ 0x04c1_1db7 } else { crc << 1 })
+        });
+        let page = |header_type: u8, granule: u64, sequence: u32, packets: &[&[u8]]| -> Vec<u8> {
+            let mut lacing: Vec<u8> = Vec::new();
+            for packet in packets {
+                lacing.extend(vec![255u8; packet.len() / 255]);
+                lacing.push((packet.len() % 255) as u8);
+            }
+            let mut bytes = [&b"OggS\0"[..], &[header_type], &granule.to_le_bytes(), &7u32.to_le_bytes(), &sequence.to_le_bytes(),
+                             &[0u8; 4], &[lacing.len() as u8], &lacing, &packets.concat()].concat();
+            let sum = crc(&bytes);
+            bytes[22..26].copy_from_slice(&sum.to_le_bytes());
+            bytes
+        };
+        // Split data into pages, checking their checksums, and join the pages into packets
+        type Pages = Vec<(u8, u64, u32)>;                           // Header type, granule position and sequence number
+        let split = |mut data: &[u8]| -> (Pages, Vec<Vec<u8>>) {
+            let (mut pages, mut packets, mut packet) = (Vec::new(), Vec::new(), Vec::new());
+            while !data.is_empty() {
+                let count = data[26] as usize;
+                let size = 27 + count + data[27..27 + count].iter().map(|value| *value as usize).sum::<usize>();
+                let mut bytes = data[..size].to_vec();
+                bytes[22..26].copy_from_slice(&[0u8; 4]);
+                assert_eq!(&data[22..26], &crc(&bytes).to_le_bytes());
+
+                let mut pos = 27 + count;
+                for value in &data[27..27 + count] {
+                    packet.extend_from_slice(&data[pos..pos + *value as usize]);
+                    pos += *value as usize;
+                    if *value < 255 { packets.push(std::mem::take(&mut packet)); }
+                }
+                pages.push((data[5], u64::from_le_bytes(data[6..14].try_into().unwrap()), u32::from_le_bytes(data[18..22].try_into().unwrap())));
+                data = &data[size..];
+            }
+            assert!(packet.is_empty());
+            (pages, packets)
+        };
+
+        let identification = [&b"\x01vorbis"[..], &[0u8; 23]].concat();
+        let comment = [&b"\x03vorbis"[..], &vorbis::build(&vorbis::Comments { vendor: String::from("test"), fields: Vec::new() }), &[1]].concat();
+        let setup = [&b"\x05vorbis"[..], &[5u8; 503]].concat();
+        let original = [page(2, 0, 0, &[&identification]), page(0, 0, 1, &[&comment, &setup]), page(4, 4410, 2, &[b"AUDIO"])].concat();
+
+        // A large picture takes several pages, with packets continuing from page to page
+        let mut tag = Tag::new();
+        tag.set_title("Jóga");
+        tag.add_frame(Picture { mime_type: String::from("image/png"), picture_type: PictureType::CoverFront, description: String::new(), data: vec![9u8; 150000] });
+
+        let output = ogg::rebuild(&original, &tag).unwrap();
+        let (pages, packets) = split(&output);
+        assert!(pages.len() >= 6);
+        assert_eq!(pages.iter().map(|page| page.2).collect::<Vec<u32>>(), (0..pages.len() as u32).collect::<Vec<u32>>());
+        assert!(pages[2..pages.len() - 1].iter().all(|page| page.0 == 1));
+        assert_eq!((pages[pages.len() - 1].0, pages[pages.len() - 1].1), (4, 4410));
+        assert_eq!(packets.len(), 4);
+        assert_eq!((&packets[0], &packets[2], &packets[3][..]), (&identification, &setup, &b"AUDIO"[..]));
+
+        let read = ogg::read_from(&mut &output[..]).unwrap();
+        assert_eq!(read.title(), Some("Jóga"));
+        assert_eq!(read.pictures().next().map(|picture| picture.data.len()), Some(150000));
+
+        // Removing the comments brings back the original page layout
+        let removed = ogg::rebuild(&output, &Tag::new()).unwrap();
+        assert_eq!(removed, original);
+    }
+
+    #[test]
+    fn sync_v1_skips_other_formats() {
+        let mut tag = Tag::new();
+        tag.set_title("Jóga");
+        let mut id3: Vec<u8> = Vec::new();
+        tag.write_to(&mut id3, Version::Id3v24).unwrap();
+
+        let body = [&b"WAVE"[..], b"fmt ", &16u32.to_le_bytes(), &[0u8; 16], b"data", &4u32.to_le_bytes(), &[1, 2, 3, 4],
+                    b"ID3 ", &(id3.len() as u32).to_le_bytes(), &id3, if id3.len() % 2 == 1 { &[0u8][..] } else { &[] }].concat();
+        let wav = [&b"RIFF"[..], &(body.len() as u32).to_le_bytes(), &body].concat();
+        let path = std::env::temp_dir().join(format!("id3handler-sync-{}.wav", std::process::id()));
+        fs::write(&path, &wav).unwrap();
+
+        let status = sync_v1(&path.to_string_lossy(), detect_format(&path.to_string_lossy()), "silent", false);
+        let data = fs::read(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert!(status == FileStatus::Skipped);
+        assert_eq!(data, wav);
+    }
+
+    #[test]
+    fn disc_numbers_from_folder() {
+        let root = std::env::temp_dir().join(format!("id3handler-discs-{}", std::process::id()));
+        let book = root.join("Reader - 2010 - Book");
+        let set = root.join("Band - 2001 - Box");
+        fs::create_dir_all(&book).unwrap();
+        fs::create_dir_all(&set).unwrap();
+        for track in 101..=120 { fs::write(book.join(format!("{} - Part.mp3", track)), b"").unwrap(); }
+        for track in [101, 102, 103, 201, 202] { fs::write(set.join(format!("{} - Song.mp3", track)), b"").unwrap(); }
+
+        let path = book.join("110 - Part.mp3").to_string_lossy().into_owned();
+        let info = ID3TagInfo::parse(&path, "empty");
+        assert_eq!((info.track, info.track_total, info.disc, info.disc_total), (110, 20, 0, 0));
+
+        let path = set.join("202 - Song.mp3").to_string_lossy().into_owned();
+        let info = ID3TagInfo::parse(&path, "empty");
+        let _ = fs::remove_dir_all(&root);
+        assert_eq!((info.track, info.track_total, info.disc, info.disc_total), (2, 2, 2, 2));
+        assert!(!uses_disc_numbers("missing folder/101 - Song.mp3"));
+    }
+
+    #[test]
+    fn replace_file_keeps_other_files() {
+        let path = std::env::temp_dir().join(format!("id3handler-replace-{}.mp3", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        let sibling = format!("{}.tmp", path);
+        fs::write(&path, b"old").unwrap();
+        fs::write(&sibling, b"other").unwrap();
+
+        replace_file(&path, |file| std::io::Write::write_all(file, b"new")).unwrap();
+        let failed = replace_file(&path, |_file| Err(std::io::Error::other("failed")));
+        let (data, other) = (fs::read(&path).unwrap(), fs::read(&sibling).unwrap());
+        let folder = Path::new(&path).parent().unwrap().to_path_buf();
+        let name = Path::new(&path).file_name().unwrap().to_string_lossy().into_owned();
+        let left = fs::read_dir(&folder).unwrap().flatten().filter(|entry| entry.file_name().to_string_lossy().starts_with(&name)).count();
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&sibling);
+
+        assert!(failed.is_err());
+        assert_eq!((data.as_slice(), other.as_slice()), (&b"new"[..], &b"other"[..]));
+        assert_eq!(left, 2);
+    }
+
+    #[test]
+    fn rename_nested_dirs() {
+        let root = std::env::temp_dir().join(format!("id3handler-dirs-{}", std::process::id()));
+        let parent = root.join("Unknown");
+        let child = parent.join("Disc");
+        fs::create_dir_all(&child).unwrap();
+
+        rename_dirs(&[(parent.clone(), String::from("Artist")), (child.clone(), String::from("Album"))], "silent", false);
+        let renamed = root.join("Artist").join("Album").is_dir();
+        let _ = fs::remove_dir_all(&root);
+        assert!(renamed);
+    }
+
+    #[test]
+    fn report_extended_texts() {
+        let mut tag = Tag::new();
+        tag.set_title("Jóga");
+        tag.add_frame(ExtendedText { description: String::from("MOOD"), value: String::from("Calm") });
+        tag.add_frame(ExtendedText { description: String::from("KEY"), value: String::from("C") });
+
+        let mut info = ID3TagInfo::empty();
+        info.extra.push((String::from("MOOD"), String::from("Happy")));
+        info.extra.push((String::from("LABEL"), String::from("One Little Indian")));
+
+        let report = update_tag(&mut tag, &info);
+        assert_eq!(report.kept, vec![String::from("TIT2"), String::from("TXXX:KEY")]);
+        assert_eq!(report.changed, vec![String::from("TXXX:MOOD")]);
+        assert_eq!(report.added, vec![String::from("TXXX:LABEL")]);
+    }
+
+    #[test]
+    fn remove_tags_with_any_frames() {
+        let frame: Vec<u8> = [&[0xff, 0xfb, 0x90, 0x00][..], &[0u8; 413]].concat();
+        let path = std::env::temp_dir().join(format!("id3handler-remove-{}.mp3", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        fs::write(&path, frame.repeat(2)).unwrap();
+
+        let mut tag = Tag::new();
+        tag.set_genre("Trip-Hop");
+        tag.write_to_path(&path, Version::Id3v24).unwrap();
+
+        let dry = remove_tag(&path, "mp3", "v2", "silent", true);
+        let removed = remove_tag(&path, "mp3", "v2", "silent", false);
+        let again = remove_tag(&path, "mp3", "v2", "silent", false);
+        let data = fs::read(&path).unwrap();
+        let _ = fs::remove_file(&path);
+
+        assert!(dry == FileStatus::Updated && removed == FileStatus::Updated && again == FileStatus::Skipped);
+        assert_eq!(data, frame.repeat(2));
+    }
+}
@@ -0,0 +1,493 @@
+use crate::id3v1;
+use id3::frame::{Comment, ExtendedText, Picture, PictureType};
+use id3::{Tag, TagLike};
+use std::fs;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+
+// iTunes text atoms and the ID3 frames holding the same information
+const TEXT_ATOMS: [(&[u8; 4], &str); 7] = [
+    (b"\xa9ART", "TPE1"), (b"\xa9alb", "TALB"), (b"\xa9nam", "TIT2"), (b"\xa9day", "TDRC"),
+    (b"\xa9gen", "TCON"), (b"aART", "TPE2"), (b"\xa9wrt", "TCOM"),
+];
+
+// Atoms written from the tag, others in the item list are kept as they are
+const MANAGED_ATOMS: [&[u8; 4]; 7] = [b"trkn", b"disk", b"cpil", b"\xa9cmt", b"covr", b"gnre", b"----"];
+
+// Data atom type codes
+const TYPE_BINARY: u32 = 0;
+const TYPE_UTF8: u32 = 1;
+const TYPE_JPEG: u32 = 13;
+const TYPE_PNG: u32 = 14;
+const TYPE_INTEGER: u32 = 21;
+
+// Single atom, with the data after its header
+#[derive(Clone, PartialEq)]
+struct Atom {
+    kind: [u8; 4],
+    data: Vec<u8>,
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Parse list of atoms
+//
+// Inputs
+// data - atom data, without the header of the parent atom
+//
+// Return: List of atoms, or None if the data is broken
+//////////////////////////////////////////////////////////////////////////////////////
+fn parse_atoms(data: &[u8]) -> Option<Vec<Atom>> {
+    let mut atoms: Vec<Atom> = Vec::new();
+    let mut pos: usize = 0;
+
+    while pos + 8 <= data.len() {
+        let mut size = u32::from_be_bytes(data[pos..pos + 4].try_into().ok()?) as usize;
+        let kind: [u8; 4] = data[pos + 4..pos + 8].try_into().ok()?;
+        let mut header: usize = 8;
+
+        if size == 1 {
+            size = u64::from_be_bytes(data.get(pos + 8..pos + 16)?.try_into().ok()?) as usize;
+            header = 16;
+        } else if size == 0 {
+            size = data.len() - pos;
+        }
+
+        if size < header { return None; }
+        atoms.push(Atom { kind, data: data.get(pos + header..pos + size)?.to_vec() });
+        pos += size;
+    }
+
+    Some(atoms)
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Build list of atoms with their headers
+//////////////////////////////////////////////////////////////////////////////////////
+fn build_atoms(atoms: &[Atom]) -> Vec<u8> {
+    let mut data: Vec<u8> = Vec::new();
+
+    for atom in atoms {
+        data.extend_from_slice(&(atom.data.len() as u32 + 8).to_be_bytes());
+        data.extend_from_slice(&atom.kind);
+        data.extend_from_slice(&atom.data);
+    }
+
+    data
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Find top level moov atom from MP4 file
+//
+// Inputs
+// file - opened MP4 file, or its data
+//
+// Return: Position and size of the moov atom, and positions of every mdat, or error if not found
+//////////////////////////////////////////////////////////////////////////////////////
+fn find_moov<R: Read + Seek>(file: &mut R) -> io::Result<(u64, u64, Vec<u64>)> {
+    let length = file.seek(SeekFrom::End(0))?;
+    let mut pos: u64 = 0;
+    let mut moov: Option<(u64, u64)> = None;
+    let mut mdats: Vec<u64> = Vec::new();
+
+    while pos + 8 <= length {
+        let mut header = [0u8; 16];
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut header[0..8])?;
+
+        let mut size = u32::from_be_bytes(header[0..4].try_into().unwrap()) as u64;
+        if size == 1 {
+            file.read_exact(&mut header[8..16])?;
+            size = u64::from_be_bytes(header[8..16].try_into().unwrap());
+        } else if size == 0 {
+            size = length - pos;
+        }
+
+        if size < 8 { break; }
+        if &header[4..8] == b"moov" { moov = Some((pos, size)); }
+        if &header[4..8] == b"mdat" { mdats.push(pos); }
+        pos += size;
+    }
+
+    match moov {
+        Some((start, size)) => Ok((start, size, mdats)),
+        None => Err(io::Error::new(io::ErrorKind::InvalidData, "No moov atom found")),
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Read item list atoms from MP4 file
+//
+// Inputs
+// file - opened MP4 file, or its data
+//
+// Return: Items of moov/udta/meta/ilst, and the parsed moov atom children
+//////////////////////////////////////////////////////////////////////////////////////
+fn read_items<R: Read + Seek>(file: &mut R) -> io::Result<(Vec<Atom>, Vec<Atom>)> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Broken moov atom");
+    let (start, size, _mdats) = find_moov(file)?;
+
+    let mut moov = vec![0u8; size as usize];
+    file.seek(SeekFrom::Start(start))?;
+    file.read_exact(&mut moov)?;
+
+    let children = parse_atoms(&moov[8..]).ok_or_else(invalid)?;
+    let items = get_child(&children, b"udta")
+        .and_then(|udta| get_child(&udta, b"meta"))
+        .and_then(|meta| get_child(&meta, b"ilst"))
+        .unwrap_or_default();
+
+    Ok((items, children))
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Get children of the given container atom
+//
+// Inputs
+// atoms - list of atoms
+// kind  - wanted container, meta has the version and flags before its children
+//
+// Return: Children of the container, or None if not found
+//////////////////////////////////////////////////////////////////////////////////////
+fn get_child(atoms: &[Atom], kind: &[u8; 4]) -> Option<Vec<Atom>> {
+    let atom = atoms.iter().find(|atom| &atom.kind == kind)?;
+    let skip = if kind == b"meta" { 4 } else { 0 };
+    parse_atoms(atom.data.get(skip..)?)
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Replace or add child atom in a list
+//////////////////////////////////////////////////////////////////////////////////////
+fn set_child(atoms: &mut Vec<Atom>, atom: Atom) {
+    match atoms.iter().position(|other| other.kind == atom.kind) {
+        Some(pos) => atoms[pos] = atom,
+        None => atoms.push(atom),
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Get payloads and type codes of the data atoms of an item
+//////////////////////////////////////////////////////////////////////////////////////
+fn get_data(item: &Atom) -> Vec<(u32, Vec<u8>)> {
+    parse_atoms(&item.data).unwrap_or_default().into_iter()
+        .filter(|atom| &atom.kind == b"data" && atom.data.len() >= 8)
+        .map(|atom| (u32::from_be_bytes(atom.data[0..4].try_into().unwrap()) & 0xff_ffff, atom.data[8..].to_vec()))
+        .collect()
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Build data atom with type code and zero locale
+//////////////////////////////////////////////////////////////////////////////////////
+fn data_atom(kind: u32, payload: &[u8]) -> Atom {
+    let mut data: Vec<u8> = Vec::new();
+    data.extend_from_slice(&kind.to_be_bytes());
+    data.extend_from_slice(&[0u8; 4]);
+    data.extend_from_slice(payload);
+    Atom { kind: *b"data", data }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Get text value of freeform atom child, skipping its version and flags
+//////////////////////////////////////////////////////////////////////////////////////
+fn get_freeform_text(children: &[Atom], kind: &[u8; 4]) -> String {
+    children.iter().find(|atom| &atom.kind == kind)
+        .map(|atom| String::from_utf8_lossy(atom.data.get(4..).unwrap_or(&[])).to_string())
+        .unwrap_or_default()
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Convert item list atoms to ID3 tag, so they can be handled like any ID3 tag
+//
+// Inputs
+// items - atoms of the ilst atom
+//
+// Return: ID3 tag with the same information
+//////////////////////////////////////////////////////////////////////////////////////
+fn items_to_tag(items: &[Atom]) -> Tag {
+    let mut tag = Tag::new();
+
+    for item in items {
+        let data = get_data(item);
+        let text: Vec<String> = data.iter().filter(|(kind, _)| *kind == TYPE_UTF8).map(|(_, payload)| String::from_utf8_lossy(payload).to_string()).collect();
+
+        if let Some((_, id)) = TEXT_ATOMS.iter().find(|(kind, _)| *kind == &item.kind) {
+            // Dates may have a time after the date, which doesn't fit the year based handling
+            let values: Vec<&str> = text.iter().map(|value| if *id == "TDRC" { value.split('T').next().unwrap_or("") } else { value.as_str() }).collect();
+            if !values.is_empty() { tag.set_text_values(*id, values); }
+        } else if &item.kind == b"trkn" || &item.kind == b"disk" {
+            if let Some((_, payload)) = data.first().filter(|(_, payload)| payload.len() >= 6) {
+                let number = u16::from_be_bytes([payload[2], payload[3]]);
+                let total = u16::from_be_bytes([payload[4], payload[5]]);
+                let id = if &item.kind == b"trkn" { "TRCK" } else { "TPOS" };
+
+                if total > 0 { tag.set_text(id, format!("{}/{}", number, total));
+                } else {       tag.set_text(id, format!("{}", number)); }
+            }
+        } else if &item.kind == b"gnre" && tag.genre().is_none() {
+            // Numeric genres are ID3v1 genre numbers plus one
+            if let Some((_, payload)) = data.first().filter(|(_, payload)| payload.len() >= 2) {
+                let index = u16::from_be_bytes([payload[0], payload[1]]) as usize;
+                if let Some(genre) = index.checked_sub(1).and_then(id3v1::genre_name) { tag.set_genre(genre); }
+            }
+        } else if &item.kind == b"cpil" {
+            // Compilation flag is a one byte integer
+            if data.first().map(|(_, payload)| payload.iter().any(|byte| *byte != 0)) == Some(true) { tag.set_text("TCMP", "1"); }
+        } else if &item.kind == b"\xa9cmt" && !text.is_empty() {
+            tag.add_frame(Comment { lang: String::from("eng"), description: String::new(), text: text.join("\0") });
+        } else if &item.kind == b"covr" {
+            for (kind, payload) in data {
+                let mime = if kind == TYPE_PNG || payload.starts_with(b"\x89PNG") { "image/png" } else { "image/jpeg" };
+                tag.add_frame(Picture { mime_type: String::from(mime), picture_type: PictureType::CoverFront, description: String::new(), data: payload });
+            }
+        } else if &item.kind == b"----" {
+            let children = parse_atoms(&item.data).unwrap_or_default();
+            let name = get_freeform_text(&children, b"name");
+            if !name.is_empty() && !text.is_empty() { tag.add_frame(ExtendedText { description: name, value: text.join("\0") }); }
+        }
+    }
+
+    tag
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Convert ID3 tag to the information item list atoms can hold
+//
+// Return: ID3 tag as it would be read back after writing
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn normalize(tag: &Tag) -> Tag {
+    items_to_tag(&tag_to_items(tag))
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Convert ID3 tag to item list atoms
+//
+// Inputs
+// tag - ID3 tag handler
+//
+// Return: Item atoms, frames without a matching atom are dropped
+//////////////////////////////////////////////////////////////////////////////////////
+fn tag_to_items(tag: &Tag) -> Vec<Atom> {
+    let mut items: Vec<Atom> = Vec::new();
+    let mut covers: Vec<Atom> = Vec::new();
+
+    for frame in tag.frames() {
+        let id = frame.id();
+        let content = frame.content();
+
+        if id == "TRCK" || id == "TPOS" {
+            let text = content.text().unwrap_or("");
+            let (number, total) = text.split_once('/').unwrap_or((text, ""));
+            let number: u16 = number.trim().parse().unwrap_or(0);
+            let total: u16 = total.trim().parse().unwrap_or(0);
+
+            let mut payload: Vec<u8> = vec![0, 0];
+            payload.extend_from_slice(&number.to_be_bytes());
+            payload.extend_from_slice(&total.to_be_bytes());
+            if id == "TRCK" { payload.extend_from_slice(&[0, 0]); }
+
+            let kind = if id == "TRCK" { *b"trkn" } else { *b"disk" };
+            items.push(Atom { kind, data: build_atoms(&[data_atom(TYPE_BINARY, &payload)]) });
+        } else if id == "TCMP" {
+            let flag: u8 = if content.text().map(|text| text.trim()) == Some("1") { 1 } else { 0 };
+            items.push(Atom { kind: *b"cpil", data: build_atoms(&[data_atom(TYPE_INTEGER, &[flag])]) });
+        } else if id == "TYER" && tag.get("TDRC").is_none() {
+            items.push(Atom { kind: *b"\xa9day", data: build_atoms(&[data_atom(TYPE_UTF8, content.text().unwrap_or("").as_bytes())]) });
+        } else if let Some((kind, _)) = TEXT_ATOMS.iter().find(|(_, other)| *other == id) {
+            let values: Vec<Atom> = content.text_values().into_iter().flatten().map(|value| data_atom(TYPE_UTF8, value.as_bytes())).collect();
+            items.push(Atom { kind: **kind, data: build_atoms(&values) });
+        } else if let Some(comment) = content.comment() {
+            let values: Vec<Atom> = comment.text.split('\0').map(|value| data_atom(TYPE_UTF8, value.as_bytes())).collect();
+            items.push(Atom { kind: *b"\xa9cmt", data: build_atoms(&values) });
+        } else if let Some(picture) = content.picture() {
+            let kind = if picture.mime_type == "image/png" { TYPE_PNG } else { TYPE_JPEG };
+            covers.push(data_atom(kind, &picture.data));
+        } else if let Some(text) = content.extended_text() {
+            let mut mean: Vec<u8> = vec![0u8; 4];
+            mean.extend_from_slice(b"com.apple.iTunes");
+            let mut name: Vec<u8> = vec![0u8; 4];
+            name.extend_from_slice(text.description.as_bytes());
+
+            let mut children = vec![Atom { kind: *b"mean", data: mean }, Atom { kind: *b"name", data: name }];
+            children.extend(text.value.split('\0').map(|value| data_atom(TYPE_UTF8, value.as_bytes())));
+            items.push(Atom { kind: *b"----", data: build_atoms(&children) });
+        }
+    }
+
+    if !covers.is_empty() { items.push(Atom { kind: *b"covr", data: build_atoms(&covers) }); }
+    items
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Get key identifying the item, freeform atoms are identified by their name
+//////////////////////////////////////////////////////////////////////////////////////
+fn item_key(item: &Atom) -> Vec<u8> {
+    if &item.kind == b"----" {
+        let mut key = item.kind.to_vec();
+        key.extend_from_slice(get_freeform_text(&parse_atoms(&item.data).unwrap_or_default(), b"name").as_bytes());
+        key
+    } else if &item.kind == b"gnre" {
+        b"\xa9gen".to_vec()
+    } else {
+        item.kind.to_vec()
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Check if the item is written from the tag
+//////////////////////////////////////////////////////////////////////////////////////
+fn is_managed(item: &Atom) -> bool {
+    TEXT_ATOMS.iter().any(|(kind, _)| *kind == &item.kind) || MANAGED_ATOMS.contains(&&item.kind)
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Read iTunes metadata from MP4 file
+//
+// Inputs
+// path - path to MP4 file
+//
+// Return: ID3 tag holding the same information
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn read(path: &str) -> io::Result<Tag> {
+    read_from(&mut fs::File::open(path)?)
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Read iTunes metadata from opened MP4 file or its data
+//
+// Return: ID3 tag holding the same information
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn read_from<R: Read + Seek>(file: &mut R) -> io::Result<Tag> {
+    let (items, _children) = read_items(file)?;
+    Ok(items_to_tag(&items))
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Write tag to MP4 file as iTunes metadata
+//
+// Inputs
+// path - path to MP4 file
+// tag  - ID3 tag handler, frames without a matching atom are dropped
+//
+// Return: Error if the file can't be read or written
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn write(path: &str, tag: &Tag) -> io::Result<()> {
+    let output = rebuild(&fs::read(path)?, tag)?;
+    crate::replace_file(path, |file| file.write_all(&output))
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Rebuild MP4 data with the tag written as iTunes metadata
+//
+// Inputs
+// data - MP4 file data
+// tag  - ID3 tag handler, frames without a matching atom are dropped
+//
+// Return: New file data, or error if the data is broken or its audio offsets can't be moved
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn rebuild(data: &[u8], tag: &Tag) -> io::Result<Vec<u8>> {
+    let (old_items, mut moov) = read_items(&mut Cursor::new(data))?;
+
+    // Items are rewritten only if their value changed, so data this tool doesn't know is kept
+    let regenerated = tag_to_items(&items_to_tag(&old_items));
+    let new_items = tag_to_items(tag);
+    let same = |key: &Vec<u8>| {
+        regenerated.iter().filter(|item| &item_key(item) == key).eq(new_items.iter().filter(|item| &item_key(item) == key))
+    };
+
+    let mut items: Vec<Atom> = old_items.iter().filter(|item| !is_managed(item) || same(&item_key(item))).cloned().collect();
+    items.extend(new_items.iter().filter(|item| !same(&item_key(item))).cloned());
+
+    // New meta atom needs a handler telling it holds iTunes metadata
+    let mut udta = get_child(&moov, b"udta").unwrap_or_default();
+    let mut meta = get_child(&udta, b"meta").unwrap_or_else(|| {
+        let mut hdlr: Vec<u8> = vec![0u8; 8];
+        hdlr.extend_from_slice(b"mdirappl");
+        hdlr.extend_from_slice(&[0u8; 9]);
+        vec![Atom { kind: *b"hdlr", data: hdlr }]
+    });
+
+    set_child(&mut meta, Atom { kind: *b"ilst", data: build_atoms(&items) });
+    let mut meta_data: Vec<u8> = vec![0u8; 4];
+    meta_data.extend_from_slice(&build_atoms(&meta));
+    set_child(&mut udta, Atom { kind: *b"meta", data: meta_data });
+    set_child(&mut moov, Atom { kind: *b"udta", data: build_atoms(&udta) });
+
+    let (start, size, mdats) = find_moov(&mut Cursor::new(data))?;
+
+    // Chunk offsets point to the audio data, which moves if it is after the moov atom,
+    // so offsets into any mdat after it are moved, also when another mdat is before it
+    let delta = build_atoms(&moov).len() as i64 + 8 - size as i64;
+    if mdats.iter().any(|mdat| *mdat > start) && delta != 0 {
+        // Offsets of movie fragments are not handled, so their audio would be lost
+        if moov.iter().any(|atom| &atom.kind == b"mvex") {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "Fragmented MP4 files are not supported"));
+        }
+
+        adjust_offsets(&mut moov, start + size, delta)?;
+    }
+
+    let mut output: Vec<u8> = data[..start as usize].to_vec();
+    output.extend_from_slice(&build_atoms(&[Atom { kind: *b"moov", data: build_atoms(&moov) }]));
+    output.extend_from_slice(&data[(start + size) as usize..]);
+    Ok(output)
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Remove iTunes metadata items from MP4 file
+//
+// Inputs
+// path - path to MP4 file
+//
+// Return: Error if the file can't be read or written
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn remove(path: &str) -> io::Result<()> {
+    write(path, &Tag::new())
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Move chunk offsets of all tracks
+//
+// Inputs
+// moov  - children of the moov atom
+// after - offsets from this position onwards are moved
+// delta - change of the moov atom size
+//
+// Return: Error if an offset table is broken, or a 32-bit offset would overflow
+//////////////////////////////////////////////////////////////////////////////////////
+fn adjust_offsets(moov: &mut [Atom], after: u64, delta: i64) -> io::Result<()> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Broken chunk offset table");
+
+    for trak in moov.iter_mut().filter(|atom| &atom.kind == b"trak") {
+        let mut trak_children = parse_atoms(&trak.data).ok_or_else(invalid)?;
+        let mut mdia = get_child(&trak_children, b"mdia").ok_or_else(invalid)?;
+        let mut minf = get_child(&mdia, b"minf").ok_or_else(invalid)?;
+        let mut stbl = get_child(&minf, b"stbl").ok_or_else(invalid)?;
+
+        for table in stbl.iter_mut().filter(|atom| &atom.kind == b"stco" || &atom.kind == b"co64") {
+            let width: usize = if &table.kind == b"stco" { 4 } else { 8 };
+            let count = u32::from_be_bytes(table.data.get(4..8).ok_or_else(invalid)?.try_into().unwrap()) as usize;
+
+            for i in 0..count {
+                let pos = 8 + i * width;
+                let entry = table.data.get_mut(pos..pos + width).ok_or_else(invalid)?;
+                let mut bytes = [0u8; 8];
+                bytes[8 - width..].copy_from_slice(entry);
+
+                let offset = u64::from_be_bytes(bytes);
+                if offset < after { continue; }
+
+                let moved = (offset as i64 + delta) as u64;
+                if width == 4 && moved > u32::MAX as u64 {
+                    return Err(io::Error::new(io::ErrorKind::Unsupported, "Chunk offset doesn't fit the 32-bit offset table"));
+                }
+
+                entry.copy_from_slice(&moved.to_be_bytes()[8 - width..]);
+            }
+        }
+
+        set_child(&mut minf, Atom { kind: *b"stbl", data: build_atoms(&stbl) });
+        set_child(&mut mdia, Atom { kind: *b"minf", data: build_atoms(&minf) });
+        set_child(&mut trak_children, Atom { kind: *b"mdia", data: build_atoms(&mdia) });
+        trak.data = build_atoms(&trak_children);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,385 @@
+use crate::vorbis;
+use id3::Tag;
+use std::fs;
+use std::io::{self, BufReader, Read, Write};
+
+// Ogg page checksum lookup table, polynomial 0x04c11db7 without reflection
+const CRC_TABLE: [u32; 256] = crc_table();
+
+// Base64 alphabet used by METADATA_BLOCK_PICTURE comments
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// Comment key holding embedded pictures in the FLAC PICTURE block format
+const PICTURE_KEY: &str = "METADATA_BLOCK_PICTURE";
+
+// Single Ogg page
+struct Page {
+    header_type: u8,
+    granule: u64,
+    serial: u32,
+    sequence: u32,
+    segments: Vec<u8>,                                              // Lacing values
+    data: Vec<u8>,
+}
+
+// Codec specific header layout
+struct Codec {
+    headers: usize,                                                 // Number of header packets
+    comment_magic: &'static [u8],                                   // Prefix of the comment header packet
+    framing: bool,                                                  // Vorbis ends the comment header with a framing bit
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Build Ogg checksum lookup table at compile time
+//////////////////////////////////////////////////////////////////////////////////////
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+
+    while i < 256 {
+        let mut value = (i as u32) << 24;
+        let mut bit = 0;
+
+        while bit < 8 {
+            value = if value & 0x8000_0000 != 0 { (value << 1) ^ 0x04c1_1db7 } else { value << 1 };
+            bit += 1;
+        }
+
+        table[i] = value;
+        i += 1;
+    }
+
+    table
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Read the next page from Ogg stream
+//
+// Inputs
+// reader - stream positioned at the start of a page
+//
+// Return: Page, None at the end of the stream, or error if the page is broken
+//////////////////////////////////////////////////////////////////////////////////////
+fn read_page(reader: &mut impl Read) -> io::Result<Option<Page>> {
+    let mut header = [0u8; 27];
+
+    match reader.read_exact(&mut header) {
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+
+    if &header[0..4] != b"OggS" {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Not an Ogg page"));
+    }
+
+    let mut segments = vec![0u8; header[26] as usize];
+    reader.read_exact(&mut segments)?;
+
+    let mut data = vec![0u8; segments.iter().map(|value| *value as usize).sum()];
+    reader.read_exact(&mut data)?;
+
+    Ok(Some(Page {
+        header_type: header[5],
+        granule: u64::from_le_bytes(header[6..14].try_into().unwrap()),
+        serial: u32::from_le_bytes(header[14..18].try_into().unwrap()),
+        sequence: u32::from_le_bytes(header[18..22].try_into().unwrap()),
+        segments,
+        data,
+    }))
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Build page with header and checksum
+//
+// Inputs
+// page - page data
+//
+// Return: page bytes as written to the file
+//////////////////////////////////////////////////////////////////////////////////////
+fn build_page(page: &Page) -> Vec<u8> {
+    let mut bytes: Vec<u8> = Vec::new();
+
+    bytes.extend_from_slice(b"OggS");
+    bytes.push(0);
+    bytes.push(page.header_type);
+    bytes.extend_from_slice(&page.granule.to_le_bytes());
+    bytes.extend_from_slice(&page.serial.to_le_bytes());
+    bytes.extend_from_slice(&page.sequence.to_le_bytes());
+    bytes.extend_from_slice(&[0u8; 4]);
+    bytes.push(page.segments.len() as u8);
+    bytes.extend_from_slice(&page.segments);
+    bytes.extend_from_slice(&page.data);
+
+    // Checksum is counted with its own field set to zero
+    let crc = bytes.iter().fold(0u32, |crc, byte| (crc << 8) ^ CRC_TABLE[((crc >> 24) as u8 ^ byte) as usize]);
+    bytes[22..26].copy_from_slice(&crc.to_le_bytes());
+
+    bytes
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Split packets into pages, each packet ending on the page after it
+//
+// Inputs
+// packets  - packet data
+// serial   - stream serial number
+// sequence - sequence number of the first page
+//
+// Return: List of pages, with granule position 0 on pages where a packet ends
+//////////////////////////////////////////////////////////////////////////////////////
+fn paginate(packets: &[Vec<u8>], serial: u32, sequence: u32) -> Vec<Page> {
+    let mut pages: Vec<Page> = Vec::new();
+    let mut page = Page { header_type: 0, granule: u64::MAX, serial, sequence, segments: Vec::new(), data: Vec::new() };
+    let mut continued = false;
+
+    for packet in packets {
+        let mut lacing: Vec<u8> = vec![255; packet.len() / 255];
+        lacing.push((packet.len() % 255) as u8);
+        let mut pos: usize = 0;
+
+        for (i, value) in lacing.iter().enumerate() {
+            if page.segments.len() == 255 {
+                let next = Page { header_type: if continued { 1 } else { 0 }, granule: u64::MAX, serial, sequence: page.sequence + 1, segments: Vec::new(), data: Vec::new() };
+                pages.push(std::mem::replace(&mut page, next));
+            }
+
+            page.segments.push(*value);
+            page.data.extend_from_slice(&packet[pos..pos + *value as usize]);
+            pos += *value as usize;
+
+            continued = i + 1 < lacing.len();
+            if !continued { page.granule = 0; }
+        }
+    }
+
+    if !page.segments.is_empty() { pages.push(page); }
+    pages
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Recognize codec from its identification header
+//
+// Inputs
+// packet - first packet of the stream
+//
+// Return: Codec header layout, or None if the codec has no Vorbis comments
+//////////////////////////////////////////////////////////////////////////////////////
+fn get_codec(packet: &[u8]) -> Option<Codec> {
+    if packet.starts_with(b"\x01vorbis") {
+        Some(Codec { headers: 3, comment_magic: b"\x03vorbis", framing: true })
+    } else if packet.starts_with(b"OpusHead") {
+        Some(Codec { headers: 2, comment_magic: b"OpusTags", framing: false })
+    } else {
+        None
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Read header packets of the first logical stream
+//
+// Inputs
+// reader - stream positioned at the start of the file
+//
+// Return: Header packets, codec layout and number of pages read, including pages of other streams
+//////////////////////////////////////////////////////////////////////////////////////
+fn read_headers(reader: &mut impl Read) -> io::Result<(Vec<Vec<u8>>, Codec, usize)> {
+    let invalid = |text: &str| io::Error::new(io::ErrorKind::InvalidData, String::from(text));
+    let mut packets: Vec<Vec<u8>> = Vec::new();
+    let mut packet: Vec<u8> = Vec::new();
+    let mut codec: Option<Codec> = None;
+    let mut serial: Option<u32> = None;
+    let mut count: usize = 0;
+
+    loop {
+        let page = read_page(reader)?.ok_or_else(|| invalid("Ogg stream ends within the headers"))?;
+        count += 1;
+
+        if *serial.get_or_insert(page.serial) != page.serial { continue; }
+
+        let mut pos: usize = 0;
+        for value in &page.segments {
+            packet.extend_from_slice(&page.data[pos..pos + *value as usize]);
+            pos += *value as usize;
+
+            if *value < 255 {
+                packets.push(std::mem::take(&mut packet));
+                if codec.is_none() { codec = Some(get_codec(&packets[0]).ok_or_else(|| invalid("Unknown Ogg codec"))?); }
+            }
+        }
+
+        if let Some(found) = codec {
+            if packets.len() >= found.headers {
+                // Audio must start on a new page, so the headers can be rewritten without touching it
+                if packets.len() > found.headers || !packet.is_empty() { return Err(invalid("Audio data within the Ogg header pages")); }
+                return Ok((packets, found, count));
+            }
+
+            codec = Some(found);
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Read Vorbis comments from Ogg Vorbis or Opus file
+//
+// Inputs
+// path - path to Ogg file
+//
+// Return: ID3 tag holding the same information
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn read(path: &str) -> io::Result<Tag> {
+    read_from(&mut BufReader::new(fs::File::open(path)?))
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Read Vorbis comments from opened Ogg file or its data
+//
+// Return: ID3 tag holding the same information
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn read_from(reader: &mut impl Read) -> io::Result<Tag> {
+    let (packets, codec, _count) = read_headers(reader)?;
+
+    let comments = packets[1].strip_prefix(codec.comment_magic).and_then(vorbis::parse)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Broken comment header"))?;
+
+    let mut fields: Vec<(String, String)> = Vec::new();
+    let mut pictures = Vec::new();
+
+    for (key, value) in comments.fields {
+        if key == PICTURE_KEY {
+            if let Some(picture) = decode_base64(&value).and_then(|data| vorbis::parse_picture(&data)) { pictures.push(picture); }
+        } else {
+            fields.push((key, value));
+        }
+    }
+
+    Ok(vorbis::to_tag(&fields, &pictures))
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Write tag to Ogg Vorbis or Opus file as Vorbis comments
+//
+// Inputs
+// path - path to Ogg file
+// tag  - ID3 tag handler, frames without a Vorbis comment field are dropped
+//
+// Return: Error if the file can't be read or written
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn write(path: &str, tag: &Tag) -> io::Result<()> {
+    let output = rebuild(&fs::read(path)?, tag)?;
+    crate::replace_file(path, |file| file.write_all(&output))
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Rebuild Ogg data with the tag written as Vorbis comments
+//
+// Inputs
+// data - Ogg file data
+// tag  - ID3 tag handler, frames without a Vorbis comment field are dropped
+//
+// Return: New file data, or error if the data is broken
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn rebuild(data: &[u8], tag: &Tag) -> io::Result<Vec<u8>> {
+    let (mut packets, codec, count) = read_headers(&mut &data[..])?;
+
+    let vendor = packets[1].strip_prefix(codec.comment_magic).and_then(vorbis::parse)
+        .map(|comments| comments.vendor).unwrap_or_else(|| String::from("id3handler"));
+
+    let (mut fields, pictures) = vorbis::from_tag(tag);
+    for picture in &pictures {
+        fields.push((String::from(PICTURE_KEY), encode_base64(&vorbis::build_picture(picture))));
+    }
+
+    let mut comment: Vec<u8> = codec.comment_magic.to_vec();
+    comment.extend_from_slice(&vorbis::build(&vorbis::Comments { vendor, fields }));
+    if codec.framing { comment.push(1); }
+    packets[1] = comment;
+
+    // Pages are read again from the start, to keep pages of other streams in their place
+    let mut reader = data;
+    let mut output: Vec<u8> = Vec::new();
+    let mut serial: u32 = 0;
+    let mut old_pages: u32 = 0;
+    let mut new_pages: u32 = 0;
+
+    for i in 0..count {
+        let page = read_page(&mut reader)?.unwrap();
+
+        if i == 0 {
+            serial = page.serial;
+            output.extend_from_slice(&build_page(&page));
+        } else if page.serial == serial {
+            if old_pages == 0 {
+                let pages = paginate(&packets[1..], serial, page.sequence);
+                new_pages = pages.len() as u32;
+                for new_page in pages { output.extend_from_slice(&build_page(&new_page)); }
+            }
+
+            old_pages += 1;
+        } else {
+            output.extend_from_slice(&build_page(&page));
+        }
+    }
+
+    // Audio pages are kept as they are, only their sequence numbers follow the new header page count
+    while let Some(mut page) = read_page(&mut reader)? {
+        if page.serial == serial { page.sequence = page.sequence.wrapping_add(new_pages).wrapping_sub(old_pages); }
+        output.extend_from_slice(&build_page(&page));
+    }
+
+    Ok(output)
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Remove all Vorbis comments from Ogg Vorbis or Opus file
+//
+// Inputs
+// path - path to Ogg file
+//
+// Return: Error if the file can't be read or written
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn remove(path: &str) -> io::Result<()> {
+    write(path, &Tag::new())
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Encode data as base64 with padding
+//////////////////////////////////////////////////////////////////////////////////////
+fn encode_base64(data: &[u8]) -> String {
+    let mut output = String::new();
+
+    for chunk in data.chunks(3) {
+        let value = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8 | *chunk.get(2).unwrap_or(&0) as u32;
+
+        for i in 0..4 {
+            if i <= chunk.len() { output.push(BASE64[(value >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {              output.push('='); }
+        }
+    }
+
+    output
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Decode base64 data, padding is optional
+//
+// Return: Decoded data, or None if the input has invalid characters
+//////////////////////////////////////////////////////////////////////////////////////
+fn decode_base64(input: &str) -> Option<Vec<u8>> {
+    let mut output: Vec<u8> = Vec::new();
+    let mut value: u32 = 0;
+    let mut bits: u32 = 0;
+
+    for c in input.trim_end_matches('=').bytes() {
+        value = value << 6 | BASE64.iter().position(|other| *other == c)? as u32;
+        bits += 6;
+
+        if bits >= 8 {
+            bits -= 8;
+            output.push((value >> bits) as u8);
+            value &= (1 << bits) - 1;
+        }
+    }
+
+    Some(output)
+}
@@ -0,0 +1,157 @@
+use id3::frame::Comment;
+use id3::{Tag, TagLike};
+use std::fs;
+use std::io::{self, Write};
+
+// RIFF INFO list items and the ID3 frames holding the same information
+const INFO_FRAMES: [(&[u8; 4], &str); 7] = [
+    (b"IART", "TPE1"), (b"INAM", "TIT2"), (b"IPRD", "TALB"), (b"ICRD", "TYER"),
+    (b"IGNR", "TCON"), (b"ITRK", "TRCK"), (b"IPRT", "TRCK"),
+];
+
+// Single chunk of RIFF or AIFF file
+struct Chunk {
+    id: [u8; 4],
+    start: usize,                                                   // Position of the chunk header
+    end: usize,                                                     // Position after the data and padding
+    data: (usize, usize),                                           // Data range, without padding
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Parse chunk list of RIFF (little endian) or AIFF (big endian) container
+//
+// Inputs
+// data - chunk list data
+// base - position of the list in the file
+// big  - true for big endian chunk sizes
+//
+// Return: List of chunks, a truncated last chunk is cut to the end of data
+//////////////////////////////////////////////////////////////////////////////////////
+fn parse_chunks(data: &[u8], base: usize, big: bool) -> Vec<Chunk> {
+    let mut chunks: Vec<Chunk> = Vec::new();
+    let mut pos: usize = 0;
+
+    while pos + 8 <= data.len() {
+        let bytes: [u8; 4] = data[pos + 4..pos + 8].try_into().unwrap();
+        let size = if big { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) } as usize;
+        let data_end = (pos + 8 + size).min(data.len());
+        let end = (data_end + size % 2).min(data.len());
+
+        chunks.push(Chunk { id: data[pos..pos + 4].try_into().unwrap(), start: base + pos, end: base + end, data: (base + pos + 8, base + data_end) });
+        pos = end;
+    }
+
+    chunks
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Get container type of the file
+//
+// Return: Some(true) for AIFF, Some(false) for RIFF, None for anything else
+//////////////////////////////////////////////////////////////////////////////////////
+fn is_big_endian(data: &[u8]) -> Option<bool> {
+    if data.len() < 12 { return None; }
+
+    if &data[0..4] == b"RIFF" && &data[8..12] == b"WAVE" { Some(false)
+    } else if &data[0..4] == b"FORM" && (&data[8..12] == b"AIFF" || &data[8..12] == b"AIFC") { Some(true)
+    } else { None }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Read RIFF INFO list items from WAV file
+//
+// Inputs
+// path - path to WAV file
+//
+// Return: ID3 tag holding the same information, or error if the file has no INFO list
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn read_info(path: &str) -> io::Result<Tag> {
+    parse_info(&fs::read(path)?)
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Parse RIFF INFO list items from WAV data
+//
+// Inputs
+// data - WAV file data
+//
+// Return: ID3 tag holding the same information, or error if the data has no INFO list
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn parse_info(data: &[u8]) -> io::Result<Tag> {
+    let mut tag = Tag::new();
+
+    if is_big_endian(data) != Some(false) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a WAV file"));
+    }
+
+    let info = parse_chunks(&data[12..], 12, false).into_iter()
+        .find(|chunk| &chunk.id == b"LIST" && data.get(chunk.data.0..chunk.data.0 + 4) == Some(b"INFO"))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No INFO list"))?;
+
+    let items = data.get(info.data.0 + 4..info.data.1).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Broken INFO list"))?;
+
+    for item in parse_chunks(items, info.data.0 + 4, false) {
+        // Values are zero terminated, and usually Latin-1 or UTF-8
+        let value = String::from_utf8_lossy(&data[item.data.0..item.data.1]).trim_end_matches('\0').trim().to_string();
+        if value.is_empty() { continue; }
+
+        if let Some((_, id)) = INFO_FRAMES.iter().find(|(other, _)| *other == &item.id) {
+            // Creation date is usually a full date, but only the year fits the tag
+            if *id == "TYER" { tag.set_text(*id, value.chars().take(4).collect::<String>());
+            } else if tag.get(id).is_none() { tag.set_text(*id, value); }
+        } else if &item.id == b"ICMT" {
+            tag.add_frame(Comment { lang: String::from("eng"), description: String::new(), text: value });
+        }
+    }
+
+    Ok(tag)
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Check if the chunk holds tag data: ID3 chunk, or INFO list of WAV file
+//////////////////////////////////////////////////////////////////////////////////////
+fn is_tag_chunk(data: &[u8], chunk: &Chunk, big: bool) -> bool {
+    &chunk.id == b"ID3 " || &chunk.id == b"id3 " || (!big && &chunk.id == b"LIST" && data.get(chunk.data.0..chunk.data.0 + 4) == Some(b"INFO"))
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Remove ID3 chunk from WAV or AIFF file, and INFO list from WAV file
+//
+// Inputs
+// path - path to WAV or AIFF file
+//
+// Return: true if a chunk was removed, or error if the file can't be read or written
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn remove_tags(path: &str) -> io::Result<bool> {
+    match strip_tags(&fs::read(path)?)? {
+        Some(output) => {
+            crate::replace_file(path, |file| file.write_all(&output))?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Remove tag chunks from WAV or AIFF data
+//
+// Inputs
+// data - WAV or AIFF file data
+//
+// Return: Data without the tag chunks, None if it has none, or error if the data isn't WAV or AIFF
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn strip_tags(data: &[u8]) -> io::Result<Option<Vec<u8>>> {
+    let big = is_big_endian(data).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Not a WAV or AIFF file"))?;
+    let chunks = parse_chunks(&data[12..], 12, big);
+
+    if !chunks.iter().any(|chunk| is_tag_chunk(data, chunk, big)) { return Ok(None); }
+
+    let mut output: Vec<u8> = data[0..12].to_vec();
+    for chunk in chunks.iter().filter(|chunk| !is_tag_chunk(data, chunk, big)) {
+        output.extend_from_slice(&data[chunk.start..chunk.end]);
+    }
+
+    let size = output.len() as u32 - 8;
+    output[4..8].copy_from_slice(&if big { size.to_be_bytes() } else { size.to_le_bytes() });
+    Ok(Some(output))
+}
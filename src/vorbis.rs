@@ -0,0 +1,264 @@
+use id3::frame::{Comment, ExtendedText, Picture, PictureType};
+use id3::{Tag, TagLike};
+
+// Vorbis comment keys and the ID3 frames holding the same information
+const KEY_FRAMES: [(&str, &str); 8] = [
+    ("ARTIST", "TPE1"), ("ALBUM", "TALB"), ("TITLE", "TIT2"), ("DATE", "TDRC"),
+    ("GENRE", "TCON"), ("ALBUMARTIST", "TPE2"), ("COMPOSER", "TCOM"), ("COMPILATION", "TCMP"),
+];
+
+// Picture types in the order of their numeric values, shared by APIC frames and FLAC pictures
+const PICTURE_TYPES: [PictureType; 21] = [
+    PictureType::Other, PictureType::Icon, PictureType::OtherIcon, PictureType::CoverFront, PictureType::CoverBack,
+    PictureType::Leaflet, PictureType::Media, PictureType::LeadArtist, PictureType::Artist, PictureType::Conductor,
+    PictureType::Band, PictureType::Composer, PictureType::Lyricist, PictureType::RecordingLocation, PictureType::DuringRecording,
+    PictureType::DuringPerformance, PictureType::ScreenCapture, PictureType::BrightFish, PictureType::Illustration, PictureType::BandLogo,
+    PictureType::PublisherLogo,
+];
+
+// Vorbis comment header data
+pub struct Comments {
+    pub vendor: String,
+    pub fields: Vec<(String, String)>,                              // Keys are kept in upper case
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Parse Vorbis comment header
+//
+// Inputs
+// data - comment data, without any container specific framing
+//
+// Return: Parsed comments, or None if the data is broken
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn parse(data: &[u8]) -> Option<Comments> {
+    let mut pos: usize = 0;
+    let vendor = read_string(data, &mut pos)?;
+    let count = read_u32_le(data, &mut pos)?;
+    let mut fields: Vec<(String, String)> = Vec::new();
+
+    for _ in 0..count {
+        let field = read_string(data, &mut pos)?;
+
+        // Fields without '=' are not valid, and are dropped
+        if let Some((key, value)) = field.split_once('=') {
+            fields.push((key.to_uppercase(), String::from(value)));
+        }
+    }
+
+    Some(Comments { vendor, fields })
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Build Vorbis comment header
+//
+// Inputs
+// comments - vendor string and comment fields
+//
+// Return: comment data, without any container specific framing
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn build(comments: &Comments) -> Vec<u8> {
+    let mut data: Vec<u8> = Vec::new();
+
+    data.extend_from_slice(&(comments.vendor.len() as u32).to_le_bytes());
+    data.extend_from_slice(comments.vendor.as_bytes());
+    data.extend_from_slice(&(comments.fields.len() as u32).to_le_bytes());
+
+    for (key, value) in &comments.fields {
+        let field = format!("{}={}", key, value);
+        data.extend_from_slice(&(field.len() as u32).to_le_bytes());
+        data.extend_from_slice(field.as_bytes());
+    }
+
+    data
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Convert Vorbis comments to ID3 tag, so they can be handled like any ID3 tag
+//
+// Inputs
+// fields   - comment fields
+// pictures - embedded pictures
+//
+// Return: ID3 tag with the same information
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn to_tag(fields: &[(String, String)], pictures: &[Picture]) -> Tag {
+    let mut tag = Tag::new();
+    let mut handled: Vec<&str> = Vec::new();
+
+    for (key, _value) in fields {
+        if handled.contains(&key.as_str()) { continue; }
+        handled.push(key);
+
+        // Repeated keys are stored as multiple values of the same frame
+        let values: Vec<&str> = fields.iter().filter(|(other, _)| other == key).map(|(_, value)| value.as_str()).collect();
+
+        if key == "TRACKNUMBER" {
+            tag.set_text("TRCK", with_total(values[0], get_value(fields, "TRACKTOTAL")));
+        } else if key == "DISCNUMBER" {
+            tag.set_text("TPOS", with_total(values[0], get_value(fields, "DISCTOTAL")));
+        } else if key == "TRACKTOTAL" || key == "DISCTOTAL" {
+            // Stored together with the number
+        } else if key == "COMMENT" {
+            tag.add_frame(Comment { lang: String::from("eng"), description: String::new(), text: values.join("\0") });
+        } else if let Some((_, id)) = KEY_FRAMES.iter().find(|(name, _)| name == key) {
+            tag.set_text_values(*id, values);
+        } else {
+            tag.add_frame(ExtendedText { description: key.clone(), value: values.join("\0") });
+        }
+    }
+
+    for picture in pictures {
+        tag.add_frame(picture.clone());
+    }
+
+    tag
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Convert ID3 tag to Vorbis comments
+//
+// Inputs
+// tag - ID3 tag handler
+//
+// Return: Comment fields and embedded pictures, frames without a matching field are dropped
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn from_tag(tag: &Tag) -> (Vec<(String, String)>, Vec<Picture>) {
+    let mut fields: Vec<(String, String)> = Vec::new();
+    let mut pictures: Vec<Picture> = Vec::new();
+
+    for frame in tag.frames() {
+        let id = frame.id();
+        let content = frame.content();
+
+        if id == "TRCK" || id == "TPOS" {
+            let text = content.text().unwrap_or("");
+            let (number, total) = text.split_once('/').unwrap_or((text, ""));
+            let (number_key, total_key) = if id == "TRCK" { ("TRACKNUMBER", "TRACKTOTAL") } else { ("DISCNUMBER", "DISCTOTAL") };
+
+            if !number.is_empty() { fields.push((String::from(number_key), String::from(number))); }
+            if !total.is_empty()  { fields.push((String::from(total_key), String::from(total))); }
+        } else if id == "TYER" && tag.get("TDRC").is_none() {
+            fields.push((String::from("DATE"), String::from(content.text().unwrap_or(""))));
+        } else if let Some((key, _)) = KEY_FRAMES.iter().find(|(_, other)| *other == id) {
+            for value in content.text_values().into_iter().flatten() {
+                fields.push((String::from(*key), String::from(value)));
+            }
+        } else if let Some(text) = content.extended_text() {
+            for value in text.value.split('\0') {
+                fields.push((text.description.to_uppercase().replace('=', "_"), String::from(value)));
+            }
+        } else if let Some(comment) = content.comment() {
+            for value in comment.text.split('\0') {
+                fields.push((String::from("COMMENT"), String::from(value)));
+            }
+        } else if let Some(picture) = content.picture() {
+            pictures.push(picture.clone());
+        }
+    }
+
+    (fields, pictures)
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Convert ID3 tag to the information Vorbis comments can hold
+//
+// Return: ID3 tag as it would be read back after writing
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn normalize(tag: &Tag) -> Tag {
+    let (fields, pictures) = from_tag(tag);
+    to_tag(&fields, &pictures)
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Parse picture in the FLAC PICTURE block format, also used by METADATA_BLOCK_PICTURE
+//
+// Inputs
+// data - picture block data
+//
+// Return: Picture, or None if the data is broken
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn parse_picture(data: &[u8]) -> Option<Picture> {
+    let mut pos: usize = 0;
+    let kind = read_u32_be(data, &mut pos)? as usize;
+    let mime_len = read_u32_be(data, &mut pos)? as usize;
+    let mime_type = String::from_utf8_lossy(data.get(pos..pos + mime_len)?).to_string();
+    pos += mime_len;
+    let description_len = read_u32_be(data, &mut pos)? as usize;
+    let description = String::from_utf8_lossy(data.get(pos..pos + description_len)?).to_string();
+
+    // Width, height, color depth and number of colors are not needed
+    pos += description_len + 16;
+    let data_len = read_u32_be(data, &mut pos)? as usize;
+    let picture_data = data.get(pos..pos + data_len)?.to_vec();
+
+    let picture_type = PICTURE_TYPES.get(kind).copied().unwrap_or(PictureType::Undefined(kind as u8));
+    Some(Picture { mime_type, picture_type, description, data: picture_data })
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Build picture in the FLAC PICTURE block format
+//
+// Inputs
+// picture - picture data
+//
+// Return: picture block data, dimensions are left as 0 (unknown)
+//////////////////////////////////////////////////////////////////////////////////////
+pub fn build_picture(picture: &Picture) -> Vec<u8> {
+    let mut data: Vec<u8> = Vec::new();
+
+    data.extend_from_slice(&(u8::from(picture.picture_type) as u32).to_be_bytes());
+    data.extend_from_slice(&(picture.mime_type.len() as u32).to_be_bytes());
+    data.extend_from_slice(picture.mime_type.as_bytes());
+    data.extend_from_slice(&(picture.description.len() as u32).to_be_bytes());
+    data.extend_from_slice(picture.description.as_bytes());
+    data.extend_from_slice(&[0u8; 16]);
+    data.extend_from_slice(&(picture.data.len() as u32).to_be_bytes());
+    data.extend_from_slice(&picture.data);
+
+    data
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Get first value of the given key
+//////////////////////////////////////////////////////////////////////////////////////
+fn get_value<'a>(fields: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    fields.iter().find(|(other, _)| other == key).map(|(_, value)| value.as_str())
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Combine number and total as n/total, if the number doesn't have a total already
+//////////////////////////////////////////////////////////////////////////////////////
+fn with_total(number: &str, total: Option<&str>) -> String {
+    match total {
+        Some(total) if !number.contains('/') && !total.is_empty() => format!("{}/{}", number, total),
+        _ => String::from(number),
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Read little endian u32 and move position past it
+//////////////////////////////////////////////////////////////////////////////////////
+fn read_u32_le(data: &[u8], pos: &mut usize) -> Option<u32> {
+    let bytes: [u8; 4] = data.get(*pos..*pos + 4)?.try_into().ok()?;
+    *pos += 4;
+    Some(u32::from_le_bytes(bytes))
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Read big endian u32 and move position past it
+//////////////////////////////////////////////////////////////////////////////////////
+fn read_u32_be(data: &[u8], pos: &mut usize) -> Option<u32> {
+    let bytes: [u8; 4] = data.get(*pos..*pos + 4)?.try_into().ok()?;
+    *pos += 4;
+    Some(u32::from_be_bytes(bytes))
+}
+
+//////////////////////////////////////////////////////////////////////////////////////
+// Read length prefixed UTF-8 string and move position past it
+//////////////////////////////////////////////////////////////////////////////////////
+fn read_string(data: &[u8], pos: &mut usize) -> Option<String> {
+    let len = read_u32_le(data, pos)? as usize;
+    let text = String::from_utf8_lossy(data.get(*pos..*pos + len)?).to_string();
+    *pos += len;
+    Some(text)
+}